@@ -0,0 +1,57 @@
+use std::time::Instant;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use system_greedy::system::System;
+
+const FLIPS: usize = 200_000;
+const CHECK_EVERY: usize = 20_000;
+
+fn run(name: &str, mut system: System, spins: &[usize], flip: fn(&mut System, usize)) -> f64 {
+    let mut elapsed = 0.0;
+    let mut max_drift = (0.0f64, 0.0f64);
+
+    for chunk in spins.chunks(CHECK_EVERY) {
+        let start = Instant::now();
+        for spin in chunk {
+            flip(&mut system, *spin);
+        }
+        elapsed += start.elapsed().as_secs_f64();
+
+        let drift = system.verify();
+        max_drift = (max_drift.0.max(drift.energy), max_drift.1.max(drift.max_row));
+    }
+
+    println!(
+        "{:<11} {:>8.1} ns/flip, energy drift {:.3e}, row drift {:.3e}",
+        name,
+        elapsed / spins.len() as f64 * 1e9,
+        max_drift.0,
+        max_drift.1,
+    );
+
+    elapsed
+}
+
+fn main() {
+    let system = System::load_mfsys("input/trimer_N1200_b700.mfsys");
+    let mut rng = StdRng::seed_from_u64(42);
+    let spins: Vec<_> = (0..FLIPS).map(|_| rng.gen_range(0..system.size())).collect();
+
+    let reference = run("reference", system.clone(), &spins, System::reverse_spin_reference);
+    let chunked = run("chunked", system.clone(), &spins, System::reverse_spin);
+
+    let mut system_compensated = system.clone();
+    system_compensated.use_compensated_rows(true);
+    let compensated = run("compensated", system_compensated, &spins, System::reverse_spin);
+
+    let mut system_f32 = system.clone();
+    system_f32.use_f32_matrix(true);
+    let chunked_f32 = run("f32", system_f32, &spins, System::reverse_spin);
+
+    println!(
+        "speedup: chunked {:.2}x, compensated {:.2}x, f32 {:.2}x",
+        reference / chunked,
+        reference / compensated,
+        reference / chunked_f32,
+    );
+}
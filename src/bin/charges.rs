@@ -0,0 +1,40 @@
+use std::path::PathBuf;
+use structopt::StructOpt;
+use system_greedy::charges::ChargeMap;
+use system_greedy::system::System;
+use system_greedy::vertices::{VertexConfig, Vertices};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "charges", about = "Magnetic charge map and defects of a spin system")]
+struct Args {
+    /// input mfsys file with spin system
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    /// prefix of the csv and png files written
+    output: String,
+    /// distance from the element center to its ends, half of the nearest neighbour distance by default
+    #[structopt(long)]
+    reach: Option<f64>,
+    /// width of the png in pixels
+    #[structopt(long, default_value = "1600")]
+    width: u32,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::from_args();
+    let system = System::load_mfsys(&args.input);
+
+    let vertices = Vertices::new(&system, &VertexConfig { reach: args.reach, ..VertexConfig::default() });
+    println!("{}", vertices.population(&system));
+
+    let charges = ChargeMap::new(&system, &vertices);
+    let (positive, negative) = charges.defect_charge();
+    println!("Defects: {} (charge +{} / {})", charges.defect_count(), positive, negative);
+    println!("Total charge: {}", charges.total_charge());
+
+    charges.write_csv(format!("{}_charges.csv", args.output))?;
+    charges.write_defects_csv(format!("{}_defects.csv", args.output))?;
+    charges.write_png(format!("{}_charges.png", args.output), &system, args.width)?;
+
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+use structopt::StructOpt;
+use system_greedy::distance::{local_overlap, write_difference_png, Comparison};
+use system_greedy::symmetry::SpinSymmetry;
+use system_greedy::system::System;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "compare", about = "Distance and overlap between two states of one spin system")]
+struct Args {
+    /// first mfsys file
+    #[structopt(parse(from_os_str))]
+    first: PathBuf,
+    /// second mfsys file with the same geometry
+    #[structopt(parse(from_os_str))]
+    second: PathBuf,
+    /// png with the elements that differ after aligning the first state by the best symmetry
+    #[structopt(long, parse(from_os_str))]
+    map: Option<PathBuf>,
+    /// radius of the local overlap summary, 3 nearest neighbour distances by default
+    #[structopt(long)]
+    radius: Option<f64>,
+    /// width of the png in pixels
+    #[structopt(long, default_value = "1600")]
+    width: u32,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::from_args();
+    let first = System::load_mfsys(&args.first);
+    let second = System::load_mfsys(&args.second);
+
+    if first.elements() != second.elements() {
+        anyhow::bail!("The systems have different geometry");
+    }
+
+    let symmetries = SpinSymmetry::point_group(&first);
+    let comparison = Comparison::new(&first, &second, &symmetries);
+    println!("Symmetries: {}", symmetries.len());
+    println!("{}", comparison);
+
+    let nearest = first.nearest_distance();
+    let local = local_overlap(&first, &comparison.symmetric.aligned, second.system_state(), args.radius.unwrap_or(3.0 * nearest));
+    let disagreeing = local.iter().filter(|q| **q < 0.5).count();
+    println!("Elements with local overlap below 0.5: {} of {}", disagreeing, first.size());
+
+    if let Some(map) = args.map {
+        let mut aligned = first.clone();
+        aligned.set_system_state(comparison.symmetric.aligned.clone());
+        write_difference_png(&aligned, second.system_state(), map, args.width)?;
+    }
+
+    Ok(())
+}
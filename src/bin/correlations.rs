@@ -0,0 +1,60 @@
+use std::f64::consts::PI;
+use std::path::PathBuf;
+use structopt::StructOpt;
+use system_greedy::correlation::{Correlation, CorrelationConfig, Projection, QGrid, StructureFactor};
+use system_greedy::system::System;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "correlations", about = "Spin correlations and magnetic structure factor of a spin system")]
+struct Args {
+    /// input mfsys file with spin system
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    /// prefix of the csv and png files written
+    output: String,
+    /// width of the distance bins
+    #[structopt(long, default_value = "50")]
+    bin_width: f64,
+    /// largest pair distance, 10 nearest neighbour distances by default
+    #[structopt(long)]
+    max_distance: Option<f64>,
+    /// bins of the pair direction
+    #[structopt(long, default_value = "12")]
+    direction_bins: usize,
+    /// q grid spans [-q_max, q_max], 4 pi over the nearest neighbour distance by default
+    #[structopt(long)]
+    q_max: Option<f64>,
+    /// q grid points along each axis
+    #[structopt(long, default_value = "201")]
+    q_points: usize,
+    /// keep only the moment component perpendicular to q
+    #[structopt(long)]
+    spin_flip: bool,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::from_args();
+    let system = System::load_mfsys(&args.input);
+
+    let nearest = system.nearest_distance();
+
+    let correlation = Correlation::of(&system, CorrelationConfig {
+        bin_width: args.bin_width,
+        max_distance: args.max_distance.unwrap_or(10.0 * nearest),
+        direction_bins: args.direction_bins,
+    });
+    correlation.write_csv(format!("{}_correlation.csv", args.output))?;
+    correlation.write_png(format!("{}_correlation.png", args.output), 8)?;
+
+    for (r, value, pairs) in correlation.radial() {
+        println!("r = {:>10.2}: C = {:>8.4} ({} pairs)", r, value, pairs);
+    }
+
+    let projection = if args.spin_flip { Projection::SpinFlip } else { Projection::Full };
+    let grid = QGrid::square(args.q_max.unwrap_or(4.0 * PI / nearest), args.q_points);
+    let structure_factor = StructureFactor::of(&system, grid, projection);
+    structure_factor.write_csv(format!("{}_structure_factor.csv", args.output))?;
+    structure_factor.write_png(format!("{}_structure_factor.png", args.output), 3)?;
+
+    Ok(())
+}
@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+use structopt::StructOpt;
+use system_greedy::decomposition::{DecompositionConfig, EnergyDecomposition};
+use system_greedy::observables::Sublattices;
+use system_greedy::system::System;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "decomposition", about = "Energy of spin systems by neighbour shell, sublattice pair and unit")]
+struct Args {
+    /// input mfsys files with spin systems
+    #[structopt(parse(from_os_str), required = true)]
+    inputs: Vec<PathBuf>,
+    /// elements per unit cell of the generator, 3 for trimer
+    #[structopt(long, default_value = "3")]
+    cell_size: usize,
+    /// sublattices by moment direction instead of the position in the cell
+    #[structopt(long)]
+    directions: bool,
+    /// prefix of the csv files written for every input
+    #[structopt(long)]
+    output: Option<String>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::from_args();
+    anyhow::ensure!(args.cell_size > 0, "Cell size must be positive");
+    let config = DecompositionConfig { cell_size: args.cell_size, ..DecompositionConfig::default() };
+
+    for (index, input) in args.inputs.iter().enumerate() {
+        let system = System::load_mfsys(input);
+        let sublattices = if args.directions {
+            Sublattices::from_directions(&system)
+        } else {
+            Sublattices::from_cells(&system, args.cell_size)?
+        };
+
+        let decomposition = EnergyDecomposition::new(&system, &sublattices, &config);
+        println!("{}", input.display());
+        println!("{}", decomposition);
+        println!();
+
+        if let Some(output) = &args.output {
+            decomposition.write_shells_csv(format!("{}_{}_shells.csv", output, index))?;
+            decomposition.write_sublattices_csv(format!("{}_{}_sublattices.csv", output, index))?;
+        }
+    }
+
+    Ok(())
+}
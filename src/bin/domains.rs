@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+use structopt::StructOpt;
+use system_greedy::domains::{DomainConfig, Domains, Phase};
+use system_greedy::symmetry::SpinSymmetry;
+use system_greedy::system::{System, Vec2};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "domains", about = "Domains of an ordered phase in a spin system")]
+struct Args {
+    /// input mfsys file with spin system
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    /// mfsys file with the ordered reference state on the same geometry
+    #[structopt(parse(from_os_str))]
+    reference: PathBuf,
+    /// prefix of the mfsys and png files written
+    output: String,
+    /// treat the global flip of the reference as the same phase
+    #[structopt(long)]
+    global_flip: bool,
+    /// treat rotated and mirrored references as the same phase
+    #[structopt(long)]
+    symmetries: bool,
+    /// lattice vector "x,y" of a translated reference, can be repeated
+    #[structopt(long)]
+    shift: Vec<String>,
+    /// fraction of matching neighbours to belong to a domain
+    #[structopt(long, default_value = "0.9")]
+    threshold: f64,
+    /// width of the png in pixels
+    #[structopt(long, default_value = "1600")]
+    width: u32,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::from_args();
+    let system = System::load_mfsys(&args.input);
+    let reference = System::load_mfsys(&args.reference);
+
+    let shifts = args.shift
+        .iter()
+        .map(|s| {
+            let (x, y) = s.split_once(',').ok_or_else(|| anyhow::anyhow!("Shift must be x,y: {}", s))?;
+            Ok(Vec2::new(x.trim().parse()?, y.trim().parse()?))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut phase = Phase::new("reference", reference.system_state().clone());
+    if args.global_flip {
+        phase = phase.with_global_flip();
+    }
+    if args.symmetries {
+        phase = phase.with_symmetries(&SpinSymmetry::point_group(&system));
+    }
+    phase = phase.with_translations(&system, &shifts);
+
+    let domains = Domains::new(&system, &phase, &DomainConfig { threshold: args.threshold, ..DomainConfig::default() });
+    println!("Variants: {}", phase.variants.len());
+    println!("{}", domains);
+
+    domains.save_mfsys(&system, format!("{}_domains.mfsys", args.output));
+    domains.write_png(&system, format!("{}_domains.png", args.output), args.width)?;
+
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::cell::RefCell;
+use structopt::StructOpt;
+use system_greedy::entropy::{exact_entropy, extrapolate, integrate_entropy, write_csv, Entropy};
+use system_greedy::generators::LatticeGenerator;
+use system_greedy::runner::{RefCellStateRegisterer, StateRegistererInner};
+use system_greedy::system::System;
+use system_greedy::thermo::{metropolis_measure, SamplingConfig};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "entropy", about = "Residual entropy of a lattice extrapolated over its sizes")]
+struct Args {
+    /// lattice generated: trimer or cairo
+    #[structopt(default_value = "trimer")]
+    lattice: String,
+    /// lattice parameter b of trimer
+    #[structopt(long, default_value = "700")]
+    b: f64,
+    /// lattice parameter c of cairo
+    #[structopt(long, default_value = "376")]
+    c: f64,
+    /// cells per side of the generated lattices
+    #[structopt(long, use_delimiter = true, default_value = "1,2,3,4")]
+    sizes: Vec<usize>,
+    /// largest system counted exactly, larger ones are integrated from annealing
+    #[structopt(long, default_value = "24")]
+    exact_limit: usize,
+    /// energy window above the ground state counted as degenerate, 1e-9 of the energy scale by default
+    #[structopt(long)]
+    tolerance: Option<f64>,
+    /// hottest annealing temperature
+    #[structopt(long, default_value = "1e-2")]
+    t_max: f64,
+    /// coldest annealing temperature
+    #[structopt(long, default_value = "1e-5")]
+    t_min: f64,
+    /// temperatures between t_max and t_min, spaced geometrically
+    #[structopt(long, default_value = "64")]
+    temperatures: usize,
+    /// measured sweeps at every temperature
+    #[structopt(long, default_value = "2000")]
+    sweeps: usize,
+    /// discarded sweeps at every temperature
+    #[structopt(long, default_value = "200")]
+    burn_in: usize,
+    /// prefix of the csv files with S(T) of the integrated sizes
+    #[structopt(long)]
+    output: Option<String>,
+}
+
+fn generate(args: &Args, size: usize) -> anyhow::Result<System> {
+    match args.lattice.as_str() {
+        "trimer" => Ok(LatticeGenerator::trimer(225., args.b, size, size)),
+        "cairo" => Ok(LatticeGenerator::cairo(472.0, 344.0, args.c, 300.0, size as u64, size as u64)),
+        lattice => anyhow::bail!("Unknown lattice {}", lattice),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::from_args();
+    let steps = args.temperatures.max(2) - 1;
+    let temperatures: Vec<f64> = (0..=steps)
+        .map(|k| args.t_max * (args.t_min / args.t_max).powf(k as f64 / steps as f64))
+        .collect();
+
+    let mut entropies: Vec<Entropy> = Vec::new();
+    for size in &args.sizes {
+        let mut system = generate(&args, *size)?;
+
+        let entropy = if system.size() <= args.exact_limit {
+            let (entropy, energy) = exact_entropy(&system, args.tolerance);
+            println!("{} exact, ground state energy {}", entropy, energy);
+            entropy
+        } else {
+            let registerer = RefCellStateRegisterer(RefCell::new(StateRegistererInner::new()));
+            let config = SamplingConfig { sweeps: args.sweeps, burn_in: args.burn_in };
+            let reports = metropolis_measure(&mut system, &registerer, &temperatures, config, &[]);
+            let points = integrate_entropy(&reports);
+            if let Some(output) = &args.output {
+                write_csv(&points, format!("{}_{}.csv", output, size))?;
+            }
+
+            let coldest = points.first().unwrap();
+            let entropy = Entropy { size: system.size(), value: coldest.entropy, error: coldest.error };
+            println!("{} integrated to T = {}, energy {}", entropy, coldest.temperature, reports.last().unwrap().energy);
+            entropy
+        };
+        entropies.push(entropy);
+    }
+
+    match extrapolate(&entropies) {
+        Some(e) => println!("Extrapolated S/N = {:.6} +- {:.6} (slope {:.4})", e.entropy, e.error, e.slope),
+        None => println!("At least two sizes are needed to extrapolate"),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use std::path::PathBuf;
+use structopt::StructOpt;
+use system_greedy::excitations::{ExcitationConfig, ExcitationSpectrum};
+use system_greedy::system::System;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "excitations", about = "Lowest single, pair and cluster flip excitations of a spin system")]
+struct Args {
+    /// input mfsys file with the minimum found
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+    /// prefix of the csv and png files written
+    output: String,
+    /// largest connected cluster flipped together
+    #[structopt(long, default_value = "3")]
+    cluster_size: usize,
+    /// cheapest pairs and clusters of every size kept
+    #[structopt(long, default_value = "20")]
+    keep: usize,
+    /// bins of the single flip histogram
+    #[structopt(long, default_value = "20")]
+    bins: usize,
+    /// width of the png in pixels
+    #[structopt(long, default_value = "1600")]
+    width: u32,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::from_args();
+    let system = System::load_mfsys(&args.input);
+
+    let config = ExcitationConfig {
+        keep: args.keep,
+        cluster_size: args.cluster_size,
+        bins: args.bins,
+        ..ExcitationConfig::default()
+    };
+    let spectrum = ExcitationSpectrum::new(&system, &config);
+    println!("Energy: {}", system.energy());
+    print!("{}", spectrum);
+
+    spectrum.write_single_csv(&system, format!("{}_single.csv", args.output))?;
+    spectrum.write_clusters_csv(format!("{}_clusters.csv", args.output))?;
+    spectrum.write_png(&system, format!("{}_excitations.png", args.output), args.width)?;
+
+    Ok(())
+}
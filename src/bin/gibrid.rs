@@ -1,8 +1,12 @@
 use rand::{Rng, thread_rng};
 use system_greedy::generators::LatticeGenerator;
-use system_greedy::{gibrid, gibrid2, GibridState, greedy, prepare_state};
+use system_greedy::{gibrid, gibrid2, GibridState, greedy, prepare_state_from_plan};
+use system_greedy::clusters::{ClusterConfig, ClusterPlan};
+use system_greedy::cluster_cache::ClusterCache;
 use system_greedy::perebor::perebor_one_thread;
-use system_greedy::runner::{Replicate, runner_multi_thread, StateRegisterer};
+use system_greedy::cancel::ctrlc_token;
+use system_greedy::criteria::{default_criterion, RunProgress};
+use system_greedy::runner::{Replicate, runner_multi_thread_observed};
 
 #[derive(Debug, Clone, Copy)]
 pub enum Lattice {
@@ -23,24 +27,39 @@ fn main() {
         trim(20, 680.),
     ];
 
+    let cancel = ctrlc_token().unwrap();
+    let mut cache = ClusterCache::open("results/cluster_cache").unwrap();
+
     for lattice in lattices {
+        // One ctrl-c stops the whole batch, the interrupted lattice is still saved
+        if cancel.is_cancelled() {
+            break;
+        }
+
         let mut system = match lattice {
             Lattice::Trim { size, b } => LatticeGenerator::trimer(225., b, size, size),
             Lattice::Cairo { size, c } => LatticeGenerator::cairo(472.0, 344.0, c, 300.0, size as u64, size as u64)
         };
 
         println!("Preparing state");
-        let gibrid_state = prepare_state(&system);
+        let plan = ClusterPlan::new(&system, &ClusterConfig::default());
+        println!("{}", plan.report(&system));
+        let gibrid_state = prepare_state_from_plan(&system, plan, Some(&mut cache));
+        println!("Cluster cache: {:?}", cache.stats());
 
         println!("Start find");
-        let state = runner_multi_thread(system.clone(), Replicate, /*MK-steps*/ 10, /*threds*/ 16, |system, registerer, _| {
-            gibrid2(system, registerer, &gibrid_state);
-            dbg!(registerer.minimal_state().map(|x| x.energy));
-            println!("Step finished");
+        let observer = |progress: &RunProgress| {
+            println!(
+                "Step {} finished: minimal energy {:?}, {} flips, {:?} per step",
+                progress.steps, progress.minimal_energy, progress.flips, progress.time_per_step()
+            );
+        };
+        let report = runner_multi_thread_observed(system.clone(), Replicate, /*MK-steps*/ default_criterion(10), &cancel, /*threds*/ 16, observer, |system, registerer, _| {
+            gibrid2(system, registerer, &gibrid_state, &cancel);
         });
-        dbg!(state.energy);
+        dbg!(report.state.energy, &report.stop_reason);
 
-        system.set_system_state(state.state);
+        system.set_system_state(report.state.state);
 
         match lattice {
             Lattice::Trim { size, b } => system.save_mfsys(
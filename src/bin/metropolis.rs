@@ -1,3 +1,5 @@
+use system_greedy::cancel::ctrlc_token;
+use system_greedy::criteria::default_criterion;
 use system_greedy::generators::LatticeGenerator;
 use system_greedy::metropolis::metropolis_mc_step;
 use system_greedy::runner::{AlgorithmState, Replicate, runner_multi_thread, StateRegisterer};
@@ -33,7 +35,7 @@ fn main() {
 
     let mut system = LatticeGenerator::trimer(450.0 / 2.0, 700., 4, 3);
     let mut state = MetropolisState::new(1.0, step, end_temp);
-    runner_multi_thread(system, "trim", state, mc_steps as usize, 6, |system, registerer, state| {
+    runner_multi_thread(system, state, default_criterion(mc_steps as usize), &ctrlc_token().unwrap(), 6, |system, registerer, state| {
         metropolis_mc_step(system, registerer, state.temp, system.size() * 10000);
     });
 }
\ No newline at end of file
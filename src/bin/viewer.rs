@@ -1,4 +1,6 @@
+use std::cell::RefCell;
 use std::f32::consts::PI;
+use bitvec::prelude::BitVec;
 use iced::{Button, Canvas, canvas, Color, Column, Element, Length, Point, Radio, Rectangle, Row, Sandbox, Settings, Size, Text, Vector};
 use iced::canvas::{Cursor, Event, Frame, Geometry, Path, Program};
 use iced::canvas::event::Status;
@@ -11,6 +13,8 @@ use quadtree_f32::{Item, ItemId, QuadTree};
 use system_greedy::generators::LatticeGenerator;
 use system_greedy::runner::State;
 use system_greedy::system::System;
+use system_greedy::trajectory::TrajectoryReader;
+use system_greedy::vertices::{VertexConfig, Vertices};
 
 fn main() -> iced::Result {
     MainApp::run(Settings {
@@ -25,6 +29,8 @@ pub enum AppMessage {
     SystemUpdated,
     ViewUpdated,
     ReverseSpin(usize),
+    TrajectoryOpen,
+    TrajectoryStep(usize),
     FillModeChanged(ArrowFillMode),
 }
 
@@ -32,7 +38,12 @@ pub struct MainApp {
     lattice_view_state: LatticeViewState,
     fill_mode: Option<ArrowFillMode>,
     system: Option<System>,
-    open_button_state: iced::button::State
+    trajectory: Option<TrajectoryReader>,
+    trajectory_step: u64,
+    open_button_state: iced::button::State,
+    open_trajectory_button_state: iced::button::State,
+    step_button_state: iced::button::State,
+    step_100_button_state: iced::button::State,
 }
 
 impl Sandbox for MainApp {
@@ -45,7 +56,12 @@ impl Sandbox for MainApp {
             lattice_view_state: LatticeViewState::new(Some(&system)),
             fill_mode: Some(ArrowFillMode::Default),
             system: Some(system),
+            trajectory: None,
+            trajectory_step: 0,
             open_button_state: iced::button::State::new(),
+            open_trajectory_button_state: iced::button::State::new(),
+            step_button_state: iced::button::State::new(),
+            step_100_button_state: iced::button::State::new(),
         }
     }
 
@@ -63,9 +79,35 @@ impl Sandbox for MainApp {
                     .unwrap();
                 if let Some(path) = path {
                     self.system = Some(System::load_mfsys(&path));
+                    self.trajectory = None;
                     self.lattice_view_state.reload(self.system.as_ref().unwrap());
                 }
             }
+            AppMessage::TrajectoryOpen => {
+                let path = FileDialog::new()
+                    .set_location("~/Desktop")
+                    .add_filter("MF Trajectory", &["mftr"])
+                    .show_open_single_file()
+                    .unwrap();
+                if let (Some(path), Some(system)) = (path, self.system.as_mut()) {
+                    let trajectory = TrajectoryReader::open(&path).unwrap();
+                    if trajectory.size() == system.size() {
+                        trajectory.reset_system(system);
+                        self.trajectory = Some(trajectory);
+                        self.trajectory_step = 0;
+                    }
+                }
+            }
+            AppMessage::TrajectoryStep(count) => {
+                if let (Some(trajectory), Some(system)) = (self.trajectory.as_mut(), self.system.as_mut()) {
+                    for _ in 0..count {
+                        match trajectory.replay_step(system).unwrap() {
+                            Some(frame) => self.trajectory_step = frame.step,
+                            None => break,
+                        }
+                    }
+                }
+            }
             AppMessage::SystemUpdated => {}
             AppMessage::ViewUpdated => {}
             AppMessage::ReverseSpin(spin) => {
@@ -83,9 +125,12 @@ impl Sandbox for MainApp {
     fn view(&mut self) -> Element<Self::Message> {
         let (canvas, stats) = if let Some(ref system) = self.system {
             let canvas = vec![LatticeView::new(system, &mut self.lattice_view_state).view()];
-            let stats = vec![
+            let mut stats = vec![
                 Text::new(format!("Energy: {}", system.energy())).into()
             ];
+            if self.trajectory.is_some() {
+                stats.push(Text::new(format!("Trajectory step: {}", self.trajectory_step)).into());
+            }
 
             (canvas, stats)
         } else {
@@ -114,11 +159,29 @@ impl Sandbox for MainApp {
                             .push::<Element<'_, AppMessage>>(
                                 Radio::new(ArrowFillMode::Energy, "Energy", self.fill_mode, |v| AppMessage::FillModeChanged(v)).into()
                             )
+                            .push::<Element<'_, AppMessage>>(
+                                Radio::new(ArrowFillMode::Vertex, "Vertices", self.fill_mode, |v| AppMessage::FillModeChanged(v)).into()
+                            )
                             .push::<Element<'_, AppMessage>>(
                                 Button::new(&mut self.open_button_state, Text::new("Open"))
                                     .on_press(AppMessage::SystemOpen)
                                     .into()
                             )
+                            .push::<Element<'_, AppMessage>>(
+                                Button::new(&mut self.open_trajectory_button_state, Text::new("Open trajectory"))
+                                    .on_press(AppMessage::TrajectoryOpen)
+                                    .into()
+                            )
+                            .push::<Element<'_, AppMessage>>(
+                                Button::new(&mut self.step_button_state, Text::new("Step"))
+                                    .on_press(AppMessage::TrajectoryStep(1))
+                                    .into()
+                            )
+                            .push::<Element<'_, AppMessage>>(
+                                Button::new(&mut self.step_100_button_state, Text::new("Step x100"))
+                                    .on_press(AppMessage::TrajectoryStep(100))
+                                    .into()
+                            )
                             .into()
                     )
                     .width(Length::Units(300))
@@ -137,10 +200,11 @@ pub enum ArrowFillMode {
     Default,
     TopDown,
     Energy,
+    Vertex,
 }
 
 impl ArrowFillMode {
-    pub fn get_colors(&self, system: &System) -> Vec<Color> {
+    pub fn get_colors(&self, system: &System, vertices: Option<&Vertices>) -> Vec<Color> {
         match self {
             ArrowFillMode::Default => std::iter::repeat(Color::BLACK).take(system.size()).collect(),
             ArrowFillMode::TopDown => system.elements().iter()
@@ -175,6 +239,19 @@ impl ArrowFillMode {
                     })
                     .collect()
             }
+            ArrowFillMode::Vertex => match vertices {
+                Some(vertices) => vertices.element_types(system)
+                    .into_iter()
+                    .map(|kind| match kind {
+                        Some(kind) => {
+                            let (r, g, b) = kind.color();
+                            Color::from_rgb8(r, g, b)
+                        }
+                        None => Color::from_rgb(0.6, 0.6, 0.6),
+                    })
+                    .collect(),
+                None => std::iter::repeat(Color::from_rgb(0.6, 0.6, 0.6)).take(system.size()).collect(),
+            },
         }
     }
 }
@@ -185,6 +262,10 @@ pub struct LatticeViewState {
     center: Point,
     fill_mode: ArrowFillMode,
     grab_state: Option<GrabState>,
+    // Vertices depend on the geometry only and are built when a system is loaded
+    vertices: Option<Vertices>,
+    // Vertex colors of the last state drawn, so that redrawing the same state does not classify again
+    vertex_colors: RefCell<Option<(BitVec, Vec<Color>)>>,
 }
 
 pub struct GrabState {
@@ -226,6 +307,8 @@ impl LatticeViewState {
             center,
             fill_mode: ArrowFillMode::Default,
             grab_state: None,
+            vertices: system.map(|system| Vertices::new(system, &VertexConfig::default())),
+            vertex_colors: RefCell::new(None),
         }
     }
 
@@ -246,6 +329,24 @@ impl LatticeViewState {
             .collect();
 
         self.quadtree = QuadTree::new(rectangles.into_iter());
+        self.vertices = Some(Vertices::new(system, &VertexConfig::default()));
+        self.vertex_colors.replace(None);
+    }
+
+    pub fn colors(&self, system: &System) -> Vec<Color> {
+        if self.fill_mode != ArrowFillMode::Vertex {
+            return self.fill_mode.get_colors(system, self.vertices.as_ref());
+        }
+
+        let mut cache = self.vertex_colors.borrow_mut();
+        match cache.as_ref() {
+            Some((state, colors)) if state == system.system_state() => colors.clone(),
+            _ => {
+                let colors = self.fill_mode.get_colors(system, self.vertices.as_ref());
+                *cache = Some((system.system_state().clone(), colors.clone()));
+                colors
+            }
+        }
     }
 }
 
@@ -383,7 +484,7 @@ impl<'a> Program<AppMessage> for LatticeView<'a> {
             );
         });
 
-        let colors = self.state.fill_mode.get_colors(self.system);
+        let colors = self.state.colors(self.system);
 
         for (i, (element, color)) in self.system.elements().iter().zip(colors.into_iter()).enumerate() {
             frame.with_save(|frame| {
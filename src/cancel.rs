@@ -0,0 +1,36 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline(always)]
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    #[inline(always)]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+// Opt-in for binaries: installs the process-wide ctrl-c handler, which can be done only once.
+pub fn ctrlc_token() -> Result<CancellationToken, ctrlc::Error> {
+    let token = CancellationToken::new();
+    let t = token.clone();
+    ctrlc::set_handler(move || {
+        println!("Wait step for closing");
+        t.cancel();
+    })?;
+    Ok(token)
+}
@@ -0,0 +1,181 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use crate::runner::{State, StateRegisterer};
+use crate::system::Vec2;
+use crate::utils::write_element_map;
+use crate::vertices::Vertices;
+use crate::System;
+
+// Dumbbell picture: an element of moment m and length l carries charges +-|m|/l at its ends, the
+// positive one at the end its moment points to. The length is twice the vertex reach.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VertexCharge {
+    pub vertex: usize,
+    pub position: Vec2,
+    pub coordination: usize,
+    // Moments in minus moments out
+    pub units: i32,
+    // Sum of the dumbbell charges at the vertex
+    pub charge: f64,
+    // Units beyond the ice rule, which allows -1..=1 for odd coordination and 0 for even;
+    // non-zero for monopoles and other defects
+    pub excess: i32,
+}
+
+impl VertexCharge {
+    #[inline(always)]
+    pub fn is_defect(&self) -> bool {
+        self.excess != 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChargeMap {
+    pub charges: Vec<VertexCharge>,
+}
+
+impl ChargeMap {
+    pub fn new(system: &System, vertices: &Vertices) -> Self {
+        let length = 2.0 * vertices.reach;
+        let charges = vertices.vertices
+            .iter()
+            .enumerate()
+            .map(|(index, vertex)| {
+                let mut units = 0;
+                let mut charge = 0.0;
+                for end in &vertex.ends {
+                    let sign = if end.is_in(system) { 1 } else { -1 };
+                    units += sign;
+                    charge += sign as f64 * system.elements()[end.element].magn().magnitude() / length;
+                }
+
+                let allowed = (vertex.coordination() % 2) as i32;
+                VertexCharge {
+                    vertex: index,
+                    position: vertex.position,
+                    coordination: vertex.coordination(),
+                    units,
+                    charge,
+                    excess: units - units.clamp(-allowed, allowed),
+                }
+            })
+            .collect();
+
+        Self { charges }
+    }
+
+    pub fn defects(&self) -> impl Iterator<Item = &VertexCharge> {
+        self.charges.iter().filter(|c| c.is_defect())
+    }
+
+    pub fn defect_count(&self) -> usize {
+        self.defects().count()
+    }
+
+    // Sum of the excess units of positive and of negative defects
+    pub fn defect_charge(&self) -> (i32, i32) {
+        self.defects().fold((0, 0), |(positive, negative), c| {
+            if c.excess > 0 { (positive + c.excess, negative) } else { (positive, negative + c.excess) }
+        })
+    }
+
+    pub fn total_charge(&self) -> f64 {
+        self.charges.iter().map(|c| c.charge).sum()
+    }
+
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        write_charges(path, self.charges.iter())
+    }
+
+    pub fn write_defects_csv(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        write_charges(path, self.defects())
+    }
+
+    // Elements in gray, defects as red (positive) and blue (negative) circles; ice rule vertices
+    // with a charge as light markers.
+    pub fn write_png(&self, path: impl AsRef<Path>, system: &System, width: u32) -> anyhow::Result<()> {
+        let colors = vec![(200, 200, 200); system.size()];
+        let markers: Vec<_> = self.charges
+            .iter()
+            .filter(|c| c.units != 0)
+            .map(|c| {
+                let color = match (c.is_defect(), c.units > 0) {
+                    (true, true) => (220, 0, 0),
+                    (true, false) => (0, 60, 220),
+                    (false, true) => (255, 170, 170),
+                    (false, false) => (170, 190, 255),
+                };
+                (c.position, color)
+            })
+            .collect();
+
+        write_element_map(path, system, &colors, &markers, width)
+    }
+}
+
+fn write_charges<'a>(path: impl AsRef<Path>, charges: impl Iterator<Item = &'a VertexCharge>) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "vertex,x,y,coordination,units,charge,excess")?;
+    for c in charges {
+        writeln!(writer, "{},{},{},{},{},{},{}", c.vertex, c.position.x, c.position.y, c.coordination, c.units, c.charge, c.excess)?;
+    }
+    writer.flush()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DefectRecord {
+    pub energy: f64,
+    pub defects: usize,
+    pub positive: i32,
+    pub negative: i32,
+}
+
+struct DefectHistory {
+    minimal: Option<State>,
+    records: Vec<DefectRecord>,
+}
+
+// Records the defects of every new minimal state, to follow them through greedy or annealing
+// runs when combined with the registerer of the runner as a tuple.
+pub struct DefectTracker {
+    vertices: Vertices,
+    history: Mutex<DefectHistory>,
+}
+
+impl DefectTracker {
+    pub fn new(vertices: Vertices) -> Self {
+        Self {
+            vertices,
+            history: Mutex::new(DefectHistory { minimal: None, records: Vec::new() }),
+        }
+    }
+
+    pub fn records(&self) -> Vec<DefectRecord> {
+        self.history.lock().unwrap().records.clone()
+    }
+}
+
+impl StateRegisterer for DefectTracker {
+    fn register(&self, system: &System) {
+        let mut history = self.history.lock().unwrap();
+        if history.minimal.as_ref().map_or(f64::MAX, |s| s.energy) <= system.energy() {
+            return;
+        }
+
+        let charges = ChargeMap::new(system, &self.vertices);
+        let (positive, negative) = charges.defect_charge();
+        history.records.push(DefectRecord {
+            energy: system.energy(),
+            defects: charges.defect_count(),
+            positive,
+            negative,
+        });
+        history.minimal = Some(State { energy: system.energy(), state: system.system_state().clone() });
+    }
+
+    fn minimal_state(&self) -> Option<State> {
+        self.history.lock().unwrap().minimal.clone()
+    }
+}
@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use crate::runner::State;
+use crate::system::Vec2;
+use crate::utils::{read_state, write_state};
+use crate::Element;
+
+const MAGIC: &[u8; 4] = b"MFCC";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub stored: usize,
+    pub errors: usize,
+}
+
+// Stores the low-energy states of every cluster geometry as `{hash}.cluster` in a directory.
+// The hash is FNV-1a over the raw bits of the geometry and radius, so it stays the same between
+// builds; the geometry itself is stored too and compared on load.
+pub struct ClusterCache {
+    dir: PathBuf,
+    stats: CacheStats,
+}
+
+impl ClusterCache {
+    pub fn open(dir: impl AsRef<Path>) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir.as_ref())?;
+        Ok(Self {
+            dir: dir.as_ref().to_owned(),
+            stats: CacheStats::default(),
+        })
+    }
+
+    #[inline(always)]
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn key(part: &[Element], radius: f64) -> u64 {
+        let mut hash = 0xcbf29ce484222325u64;
+        let mut feed = |x: f64| {
+            for b in x.to_bits().to_le_bytes() {
+                hash ^= b as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        };
+
+        feed(radius);
+        for e in part {
+            feed(e.pos.x.0);
+            feed(e.pos.y.0);
+            feed(e.magn.x.0);
+            feed(e.magn.y.0);
+        }
+
+        hash
+    }
+
+    fn path(&self, part: &[Element], radius: f64) -> PathBuf {
+        self.dir.join(format!("{:016x}.cluster", Self::key(part, radius)))
+    }
+
+    pub fn load(&mut self, part: &[Element], radius: f64) -> Option<Vec<State>> {
+        match read_cluster(&self.path(part, radius), part, radius) {
+            Ok(Some(states)) => {
+                self.stats.hits += 1;
+                Some(states)
+            }
+            Ok(None) => {
+                self.stats.misses += 1;
+                None
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                self.stats.misses += 1;
+                None
+            }
+            Err(_) => {
+                self.stats.misses += 1;
+                self.stats.errors += 1;
+                None
+            }
+        }
+    }
+
+    pub fn store(&mut self, part: &[Element], radius: f64, states: &[State]) {
+        match write_cluster(&self.path(part, radius), part, radius, states) {
+            Ok(()) => self.stats.stored += 1,
+            Err(_) => self.stats.errors += 1,
+        }
+    }
+}
+
+fn read_f64(reader: &mut impl Read) -> std::io::Result<f64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> std::io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+// Written to a temporary file renamed into place, so an interrupted run leaves no partial entry.
+fn write_cluster(path: &Path, part: &[Element], radius: f64, states: &[State]) -> std::io::Result<()> {
+    let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+    let result = write_cluster_file(&temp, part, radius, states).and_then(|()| std::fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+fn write_cluster_file(path: &Path, part: &[Element], radius: f64, states: &[State]) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(&radius.to_le_bytes())?;
+    writer.write_all(&(part.len() as u64).to_le_bytes())?;
+    for e in part {
+        for x in [e.pos.x.0, e.pos.y.0, e.magn.x.0, e.magn.y.0] {
+            writer.write_all(&x.to_le_bytes())?;
+        }
+    }
+
+    writer.write_all(&(states.len() as u64).to_le_bytes())?;
+    for state in states {
+        writer.write_all(&state.energy.to_le_bytes())?;
+        write_state(&mut writer, &state.state)?;
+    }
+
+    writer.flush()
+}
+
+// Ok(None) when the file belongs to another geometry with the same hash.
+fn read_cluster(path: &Path, part: &[Element], radius: f64) -> std::io::Result<Option<Vec<State>>> {
+    let file = File::open(path)?;
+    let length = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    let mut version = [0u8; 4];
+    reader.read_exact(&mut version)?;
+    if &magic != MAGIC || u32::from_le_bytes(version) != VERSION {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, "Not a cluster cache file"));
+    }
+
+    if read_f64(&mut reader)?.to_bits() != radius.to_bits() {
+        return Ok(None);
+    }
+
+    let size = read_u64(&mut reader)? as usize;
+    if size != part.len() {
+        return Ok(None);
+    }
+
+    for e in part {
+        let pos = Vec2::new(read_f64(&mut reader)?, read_f64(&mut reader)?);
+        let magn = Vec2::new(read_f64(&mut reader)?, read_f64(&mut reader)?);
+        if Element::new(pos, magn) != *e {
+            return Ok(None);
+        }
+    }
+
+    // The count is checked against the file length before anything is allocated for it
+    let count = read_u64(&mut reader)?;
+    let header = 4 + 4 + 8 + 8 + 32 * size as u64 + 8;
+    let state_length = 8 + size.div_ceil(8) as u64;
+    if count.checked_mul(state_length) != Some(length.saturating_sub(header)) {
+        return Err(std::io::Error::new(ErrorKind::InvalidData, "Truncated cluster cache file"));
+    }
+
+    let count = count as usize;
+    let mut states = Vec::with_capacity(count);
+    for _ in 0..count {
+        let energy = read_f64(&mut reader)?;
+        let state = read_state(&mut reader, size)?;
+        states.push(State { energy, state });
+    }
+
+    Ok(Some(states))
+}
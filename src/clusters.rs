@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, HashSet};
+use std::f64::consts::PI;
+use std::fmt::{Display, Formatter};
+use bitvec::prelude::BitVec;
+use ordered_float::OrderedFloat;
+use vek::Mat2;
+use crate::system::Vec2;
+use crate::{Element, System};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClusterStrategy {
+    // Smallest radius at which some neighbourhood holds `size` elements
+    TargetSize(usize),
+    FixedRadius(f64),
+    KNearest(usize),
+    // Generators emit elements cell by cell: `cells` nearest cells of `cell_size` elements
+    UnitCell { cell_size: usize, cells: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClusterConfig {
+    pub strategy: ClusterStrategy,
+    // Clusters above this size are cut to their nearest elements, 2^max_size states are enumerated at most.
+    // The report saturates at u128::MAX for sizes of 128 and more
+    pub max_size: usize,
+    // Rotated and mirrored copies of a cluster share one enumeration
+    pub symmetric: bool,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            strategy: ClusterStrategy::TargetSize(19),
+            max_size: 24,
+            symmetric: true,
+        }
+    }
+}
+
+// Cluster of every element as indexes in ascending order.
+#[derive(Debug, Clone)]
+pub struct ClusterPlan {
+    pub clusters: Vec<Vec<usize>>,
+    pub radius: f64,
+    pub truncated: usize,
+    pub symmetric: bool,
+}
+
+impl ClusterPlan {
+    pub fn new(system: &System, config: &ClusterConfig) -> Self {
+        let size = system.size();
+        let neighbors = system.element_neighbors();
+
+        let mut clusters: Vec<Vec<usize>> = match config.strategy {
+            ClusterStrategy::TargetSize(target) => {
+                let target = target.clamp(1, size);
+                let radius = neighbors.iter().map(|n| n[target - 1].1).min().unwrap().0;
+                (0..size).map(|i| system.neighbors(i, radius).map(|(j, _)| j).collect()).collect()
+            }
+            ClusterStrategy::FixedRadius(radius) => {
+                (0..size).map(|i| system.neighbors(i, radius).map(|(j, _)| j).collect()).collect()
+            }
+            ClusterStrategy::KNearest(k) => {
+                neighbors.iter().map(|n| n.iter().take(k.max(1)).map(|(j, _)| *j).collect()).collect()
+            }
+            ClusterStrategy::UnitCell { cell_size, cells } => {
+                assert!(cell_size > 0, "Unit cell must hold at least one element");
+                let cell_count = size.div_ceil(cell_size);
+                let centers: Vec<Vec2> = (0..cell_count)
+                    .map(|c| {
+                        let members = &system.elements()[c * cell_size..((c + 1) * cell_size).min(size)];
+                        members.iter().map(|e| e.pos.map(|x| x.0)).sum::<Vec2>() / members.len() as f64
+                    })
+                    .collect();
+
+                let cell_clusters: Vec<Vec<usize>> = (0..cell_count)
+                    .map(|c| {
+                        let mut nearest: Vec<_> = (0..cell_count).collect();
+                        nearest.sort_by_key(|o| (OrderedFloat(centers[c].distance(centers[*o])), *o));
+                        nearest
+                            .into_iter()
+                            .take(cells.max(1))
+                            .flat_map(|o| o * cell_size..((o + 1) * cell_size).min(size))
+                            .collect()
+                    })
+                    .collect();
+
+                (0..size).map(|i| cell_clusters[i / cell_size].clone()).collect()
+            }
+        };
+
+        let mut truncated = 0;
+        for (i, cluster) in clusters.iter_mut().enumerate() {
+            if cluster.len() > config.max_size {
+                let members: HashSet<_> = cluster.iter().copied().collect();
+                *cluster = neighbors[i]
+                    .iter()
+                    .map(|(j, _)| *j)
+                    .filter(|j| members.contains(j))
+                    .take(config.max_size)
+                    .collect();
+                truncated += 1;
+            }
+            cluster.sort_unstable();
+        }
+
+        let radius = clusters
+            .iter()
+            .enumerate()
+            .flat_map(|(i, c)| c.iter().map(move |j| (i, *j)))
+            .map(|(i, j)| OrderedFloat(system.elements()[i].pos.map(|x| x.0).distance(system.elements()[j].pos.map(|x| x.0))))
+            .max()
+            .map_or(0.0, |r| r.0);
+
+        Self { clusters, radius, truncated, symmetric: config.symmetric }
+    }
+
+    pub fn report(&self, system: &System) -> ClusterReport {
+        let mut sizes = BTreeMap::new();
+        let mut distinct = HashSet::new();
+        let mut estimated_states = 0u128;
+
+        for cluster in &self.clusters {
+            *sizes.entry(cluster.len()).or_insert(0) += 1;
+            if distinct.insert(canonical_cluster(system, cluster, self.symmetric).0) {
+                let states = u32::try_from(cluster.len()).ok().and_then(|l| 1u128.checked_shl(l)).unwrap_or(u128::MAX);
+                estimated_states = estimated_states.saturating_add(states);
+            }
+        }
+
+        ClusterReport {
+            sizes,
+            distinct_clusters: distinct.len(),
+            estimated_states,
+            radius: self.radius,
+            truncated: self.truncated,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClusterReport {
+    pub sizes: BTreeMap<usize, usize>,
+    pub distinct_clusters: usize,
+    pub estimated_states: u128,
+    pub radius: f64,
+    pub truncated: usize,
+}
+
+impl Display for ClusterReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Cluster radius: {}", self.radius)?;
+        for (size, count) in &self.sizes {
+            writeln!(f, "  size {:>3}: {} clusters", size, count)?;
+        }
+        writeln!(f, "Truncated clusters: {}", self.truncated)?;
+        writeln!(f, "Distinct clusters: {}", self.distinct_clusters)?;
+        write!(f, "States to enumerate: {}", self.estimated_states)
+    }
+}
+
+// Canonical position k of a cluster is the system element order[k]; inversion[k] is set when
+// the canonical moment is opposite to the default moment of that element.
+#[derive(Debug, Clone)]
+pub struct ClusterMapping {
+    pub order: Vec<usize>,
+    pub inversion: BitVec,
+}
+
+fn round(v: Vec2) -> Vec2 {
+    v.map(|x| (x * 1e6).round() / 1e6 + 0.0)
+}
+
+fn cluster_key(part: &[Element]) -> Vec<[OrderedFloat<f64>; 4]> {
+    part.iter().map(|e| [e.pos.x, e.pos.y, e.magn.x, e.magn.y]).collect()
+}
+
+// Geometry of the cluster relative to its centroid, brought to the lexicographically smallest form
+// over rotations by multiples of 30 degrees and mirrors (only the element order and moment signs
+// when `symmetric` is false), together with the way back to the concrete elements.
+pub fn canonical_cluster(system: &System, cluster: &[usize], symmetric: bool) -> (Vec<Element>, ClusterMapping) {
+    let elements = system.elements();
+    let center = cluster.iter().map(|i| elements[*i].pos.map(|x| x.0)).sum::<Vec2>() / cluster.len() as f64;
+
+    let mut transforms = vec![Mat2::identity()];
+    if symmetric {
+        let mirror = Mat2::new(-1.0, 0.0, 0.0, 1.0);
+        transforms.extend((1..12).map(|k| Mat2::rotation_z(k as f64 * PI / 6.0)));
+        transforms.extend((0..12).map(|k| Mat2::rotation_z(k as f64 * PI / 6.0) * mirror));
+    }
+
+    let mut best: Option<(Vec<Element>, ClusterMapping)> = None;
+    for m in transforms {
+        let mut items: Vec<_> = cluster
+            .iter()
+            .map(|i| {
+                let e = elements[*i];
+                let pos = round(m * (e.pos.map(|x| x.0) - center));
+                let magn = round(m * e.magn());
+                let inverted = !(magn.y > 0.0 || magn.y == 0.0 && magn.x > 0.0);
+                let magn = if inverted { -magn + 0.0 } else { magn };
+                (Element::new(pos, magn), *i, inverted)
+            })
+            .collect();
+        items.sort_by_key(|(e, _, _)| cluster_key(std::slice::from_ref(e)));
+
+        let part: Vec<_> = items.iter().map(|(e, _, _)| *e).collect();
+        if best.as_ref().is_none_or(|(b, _)| cluster_key(&part) < cluster_key(b)) {
+            let mapping = ClusterMapping {
+                order: items.iter().map(|(_, i, _)| *i).collect(),
+                inversion: items.iter().map(|(_, _, inv)| *inv).collect(),
+            };
+            best = Some((part, mapping));
+        }
+    }
+
+    best.unwrap()
+}
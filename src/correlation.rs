@@ -0,0 +1,257 @@
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use rayon::prelude::*;
+use crate::system::Vec2;
+use crate::utils::write_heatmap;
+use crate::System;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CorrelationConfig {
+    pub bin_width: f64,
+    pub max_distance: f64,
+    // Bins of the pair direction over [0, PI), a pair and its reverse fall into the same one
+    pub direction_bins: usize,
+}
+
+// Spin correlation C(r) = <m_i . m_j> of unit signed moments for pairs at distance r, binned by
+// distance and direction and averaged over every state added.
+#[derive(Debug, Clone)]
+pub struct Correlation {
+    pub config: CorrelationConfig,
+    sums: Vec<f64>,
+    counts: Vec<usize>,
+}
+
+impl Correlation {
+    pub fn new(config: CorrelationConfig) -> Self {
+        assert!(
+            config.bin_width > 0.0 && config.bin_width.is_finite(),
+            "Correlation bin width must be positive and finite, got {}",
+            config.bin_width
+        );
+        assert!(
+            config.max_distance > 0.0 && config.max_distance.is_finite(),
+            "Correlation max distance must be positive and finite, got {}",
+            config.max_distance
+        );
+
+        let cells = config.direction_bins.max(1) * Self::distance_bin_count(&config);
+        Self {
+            config,
+            sums: vec![0.0; cells],
+            counts: vec![0; cells],
+        }
+    }
+
+    pub fn of(system: &System, config: CorrelationConfig) -> Self {
+        let mut correlation = Self::new(config);
+        correlation.add(system);
+        correlation
+    }
+
+    fn distance_bin_count(config: &CorrelationConfig) -> usize {
+        (config.max_distance / config.bin_width).ceil() as usize
+    }
+
+    pub fn distance_bins(&self) -> usize {
+        Self::distance_bin_count(&self.config)
+    }
+
+    pub fn direction_bins(&self) -> usize {
+        self.config.direction_bins.max(1)
+    }
+
+    pub fn add(&mut self, system: &System) {
+        let directions = self.direction_bins();
+        let distances = self.distance_bins();
+        let elements = system.elements();
+        let signs = system.system_signs();
+
+        for (i, neighbors) in system.element_neighbors().iter().enumerate() {
+            let mi = elements[i].magn().normalized() * signs[i];
+            let pi = elements[i].pos.map(|x| x.0);
+
+            // Neighbours are sorted by distance, the first one is the element itself
+            for (j, distance) in neighbors.iter().skip(1).take_while(|(_, d)| d.0 < self.config.max_distance) {
+                let distance_bin = ((distance.0 / self.config.bin_width) as usize).min(distances - 1);
+                let r = elements[*j].pos.map(|x| x.0) - pi;
+                let angle = r.y.atan2(r.x).rem_euclid(PI);
+                let direction_bin = ((angle / PI * directions as f64) as usize).min(directions - 1);
+
+                let cell = distance_bin * directions + direction_bin;
+                self.sums[cell] += mi.dot(elements[*j].magn().normalized() * signs[*j]);
+                self.counts[cell] += 1;
+            }
+        }
+    }
+
+    pub fn value(&self, distance_bin: usize, direction_bin: usize) -> Option<f64> {
+        let cell = distance_bin * self.direction_bins() + direction_bin;
+        (self.counts[cell] > 0).then(|| self.sums[cell] / self.counts[cell] as f64)
+    }
+
+    // C(r) over all directions: (bin center, value, pair count) of every non-empty distance bin.
+    pub fn radial(&self) -> Vec<(f64, f64, usize)> {
+        self.sums
+            .chunks(self.direction_bins())
+            .zip(self.counts.chunks(self.direction_bins()))
+            .enumerate()
+            .filter_map(|(bin, (sums, counts))| {
+                let count = counts.iter().sum::<usize>();
+                (count > 0).then(|| ((bin as f64 + 0.5) * self.config.bin_width, sums.iter().sum::<f64>() / count as f64, count))
+            })
+            .collect()
+    }
+
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "r,angle,correlation,pairs")?;
+        for distance_bin in 0..self.distance_bins() {
+            for direction_bin in 0..self.direction_bins() {
+                if let Some(value) = self.value(distance_bin, direction_bin) {
+                    writeln!(
+                        writer,
+                        "{},{},{},{}",
+                        (distance_bin as f64 + 0.5) * self.config.bin_width,
+                        (direction_bin as f64 + 0.5) * PI / self.direction_bins() as f64,
+                        value,
+                        self.counts[distance_bin * self.direction_bins() + direction_bin],
+                    )?;
+                }
+            }
+        }
+        writer.flush()
+    }
+
+    // Distance along the x axis, direction along y, empty bins gray.
+    pub fn write_png(&self, path: impl AsRef<Path>, scale: u32) -> anyhow::Result<()> {
+        let (width, height) = (self.distance_bins(), self.direction_bins());
+        let values: Vec<_> = (0..height)
+            .flat_map(|direction| (0..width).map(move |distance| (distance, direction)))
+            .map(|(distance, direction)| self.value(distance, direction).unwrap_or(f64::NAN))
+            .collect();
+
+        write_heatmap(path, &values, width, height, scale)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    // |sum m_i e^{iq.r_i}|^2 with the full in-plane moment
+    Full,
+    // Only the moment component perpendicular to q, what the spin-flip channel of polarized
+    // neutron scattering sees for in-plane moments; the full moment at q = 0
+    SpinFlip,
+}
+
+// Regular grid of points points.0 x points.1 spanning [min, max] in both axes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QGrid {
+    pub min: Vec2,
+    pub max: Vec2,
+    pub points: (usize, usize),
+}
+
+impl QGrid {
+    pub fn square(q_max: f64, points: usize) -> Self {
+        Self {
+            min: Vec2::new(-q_max, -q_max),
+            max: Vec2::new(q_max, q_max),
+            points: (points, points),
+        }
+    }
+
+    pub fn point(&self, col: usize, row: usize) -> Vec2 {
+        let step = |min: f64, max: f64, n: usize, k: usize| {
+            if n > 1 { min + (max - min) * k as f64 / (n - 1) as f64 } else { min }
+        };
+        Vec2::new(step(self.min.x, self.max.x, self.points.0, col), step(self.min.y, self.max.y, self.points.1, row))
+    }
+}
+
+// Magnetic structure factor S(q) = |sum_i m_i e^{iq.r_i}|^2 / N of unit signed moments, row-major
+// over the grid with row 0 at min.y, averaged over every state added.
+#[derive(Debug, Clone)]
+pub struct StructureFactor {
+    pub grid: QGrid,
+    pub projection: Projection,
+    sums: Vec<f64>,
+    samples: usize,
+}
+
+impl StructureFactor {
+    pub fn new(grid: QGrid, projection: Projection) -> Self {
+        Self {
+            grid,
+            projection,
+            sums: vec![0.0; grid.points.0 * grid.points.1],
+            samples: 0,
+        }
+    }
+
+    pub fn of(system: &System, grid: QGrid, projection: Projection) -> Self {
+        let mut structure_factor = Self::new(grid, projection);
+        structure_factor.add(system);
+        structure_factor
+    }
+
+    pub fn add(&mut self, system: &System) {
+        let moments: Vec<(Vec2, Vec2)> = system.elements()
+            .iter()
+            .zip(system.system_signs())
+            .map(|(e, s)| (e.pos.map(|x| x.0), e.magn().normalized() * *s))
+            .collect();
+        let size = moments.len().max(1) as f64;
+        let (grid, projection) = (self.grid, self.projection);
+
+        self.sums
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(cell, sum)| {
+                let q = grid.point(cell % grid.points.0, cell / grid.points.0);
+                let perpendicular = match projection {
+                    Projection::SpinFlip if q.magnitude_squared() > 0.0 => Some(Vec2::new(-q.y, q.x).normalized()),
+                    _ => None,
+                };
+
+                let (mut re, mut im) = (Vec2::zero(), Vec2::zero());
+                for (pos, m) in &moments {
+                    let m = perpendicular.map_or(*m, |p| p * m.dot(p));
+                    let (sin, cos) = q.dot(*pos).sin_cos();
+                    re += m * cos;
+                    im += m * sin;
+                }
+
+                *sum += (re.magnitude_squared() + im.magnitude_squared()) / size;
+            });
+
+        self.samples += 1;
+    }
+
+    pub fn values(&self) -> Vec<f64> {
+        let samples = self.samples.max(1) as f64;
+        self.sums.iter().map(|s| s / samples).collect()
+    }
+
+    pub fn value(&self, col: usize, row: usize) -> f64 {
+        self.sums[row * self.grid.points.0 + col] / self.samples.max(1) as f64
+    }
+
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "qx,qy,s")?;
+        for row in 0..self.grid.points.1 {
+            for col in 0..self.grid.points.0 {
+                let q = self.grid.point(col, row);
+                writeln!(writer, "{},{},{}", q.x, q.y, self.value(col, row))?;
+            }
+        }
+        writer.flush()
+    }
+
+    pub fn write_png(&self, path: impl AsRef<Path>, scale: u32) -> anyhow::Result<()> {
+        write_heatmap(path, &self.values(), self.grid.points.0, self.grid.points.1, scale)
+    }
+}
@@ -0,0 +1,194 @@
+use std::time::{Duration, Instant};
+use crate::runner::StateRegistererInner;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    MinsConverged,
+    WallClock,
+    StepBudget,
+    TargetEnergy,
+    Stagnation,
+    RelativeImprovement,
+    Interrupted,
+    All(Vec<StopReason>),
+}
+
+#[derive(Debug, Clone)]
+pub struct RunProgress {
+    pub started: Instant,
+    pub steps: usize,
+    pub steps_without_improvement: usize,
+    pub minimal_energy: Option<f64>,
+    pub previous_minimal_energy: Option<f64>,
+    pub minimal_history: Vec<(Duration, f64)>,
+    pub registered_states: usize,
+    pub flips: u64,
+    pub replica_best: Vec<f64>,
+}
+
+impl Default for RunProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RunProgress {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            steps: 0,
+            steps_without_improvement: 0,
+            minimal_energy: None,
+            previous_minimal_energy: None,
+            minimal_history: Vec::new(),
+            registered_states: 0,
+            flips: 0,
+            replica_best: Vec::new(),
+        }
+    }
+
+    pub fn update(&mut self, registerer: &StateRegistererInner, changed: bool, flips: u64) {
+        self.steps += 1;
+        self.flips = flips;
+        self.registered_states = registerer.registered_count();
+        if changed {
+            self.steps_without_improvement = 0;
+        } else {
+            self.steps_without_improvement += 1;
+        }
+
+        self.minimal_energy = registerer.minimal_energy();
+        self.previous_minimal_energy = registerer.previous_minimal_energy();
+        if let Some(energy) = self.minimal_energy {
+            self.minimal_history.push((self.started.elapsed(), energy));
+        }
+    }
+
+    // Lowest energy every replica reached during the step, registered or at its end.
+    pub fn record_replicas(&mut self, energies: &[f64]) {
+        self.replica_best.resize(energies.len(), f64::MAX);
+        for (best, energy) in self.replica_best.iter_mut().zip(energies) {
+            *best = best.min(*energy);
+        }
+    }
+
+    pub fn time_per_step(&self) -> Duration {
+        self.started.elapsed() / self.steps.max(1) as u32
+    }
+}
+
+pub trait StopCriterion {
+    fn check(&mut self, progress: &RunProgress) -> Option<StopReason>;
+
+    fn and<C: StopCriterion>(self, other: C) -> And<Self, C> where Self: Sized {
+        And(self, other)
+    }
+
+    fn or<C: StopCriterion>(self, other: C) -> Or<Self, C> where Self: Sized {
+        Or(self, other)
+    }
+}
+
+impl<C: StopCriterion + ?Sized> StopCriterion for Box<C> {
+    fn check(&mut self, progress: &RunProgress) -> Option<StopReason> {
+        (**self).check(progress)
+    }
+}
+
+pub struct And<A, B>(pub A, pub B);
+
+impl<A: StopCriterion, B: StopCriterion> StopCriterion for And<A, B> {
+    fn check(&mut self, progress: &RunProgress) -> Option<StopReason> {
+        let a = self.0.check(progress)?;
+        let b = self.1.check(progress)?;
+
+        let mut reasons = Vec::new();
+        for reason in [a, b] {
+            match reason {
+                StopReason::All(r) => reasons.extend(r),
+                r => reasons.push(r),
+            }
+        }
+        Some(StopReason::All(reasons))
+    }
+}
+
+pub struct Or<A, B>(pub A, pub B);
+
+impl<A: StopCriterion, B: StopCriterion> StopCriterion for Or<A, B> {
+    fn check(&mut self, progress: &RunProgress) -> Option<StopReason> {
+        self.0.check(progress).or_else(|| self.1.check(progress))
+    }
+}
+
+// Two successive minima closer than eps, the behaviour the runners always had.
+pub struct MinsDiff(pub f64);
+
+impl StopCriterion for MinsDiff {
+    fn check(&mut self, progress: &RunProgress) -> Option<StopReason> {
+        progress.minimal_energy
+            .zip(progress.previous_minimal_energy)
+            .filter(|(e1, e2)| (e1 - e2).abs() < self.0)
+            .map(|_| StopReason::MinsConverged)
+    }
+}
+
+pub struct WallClock(pub Duration);
+
+impl StopCriterion for WallClock {
+    fn check(&mut self, progress: &RunProgress) -> Option<StopReason> {
+        (progress.started.elapsed() >= self.0).then_some(StopReason::WallClock)
+    }
+}
+
+pub struct StepBudget(pub usize);
+
+impl StopCriterion for StepBudget {
+    fn check(&mut self, progress: &RunProgress) -> Option<StopReason> {
+        (progress.steps >= self.0).then_some(StopReason::StepBudget)
+    }
+}
+
+pub struct TargetEnergy(pub f64);
+
+impl StopCriterion for TargetEnergy {
+    fn check(&mut self, progress: &RunProgress) -> Option<StopReason> {
+        progress.minimal_energy
+            .filter(|e| *e <= self.0)
+            .map(|_| StopReason::TargetEnergy)
+    }
+}
+
+// Steps in a row without a new minimum.
+pub struct Stagnation(pub usize);
+
+impl StopCriterion for Stagnation {
+    fn check(&mut self, progress: &RunProgress) -> Option<StopReason> {
+        (progress.steps_without_improvement >= self.0).then_some(StopReason::Stagnation)
+    }
+}
+
+// Relative decrease of the minimal energy over the last `window` steps is below `threshold`.
+pub struct RelativeImprovement {
+    pub window: usize,
+    pub threshold: f64,
+}
+
+impl StopCriterion for RelativeImprovement {
+    fn check(&mut self, progress: &RunProgress) -> Option<StopReason> {
+        let history = &progress.minimal_history;
+        if history.len() <= self.window {
+            return None;
+        }
+
+        let old = history[history.len() - 1 - self.window].1;
+        let new = history[history.len() - 1].1;
+        let improvement = (old - new) / old.abs().max(f64::MIN_POSITIVE);
+
+        (improvement < self.threshold).then_some(StopReason::RelativeImprovement)
+    }
+}
+
+pub fn default_criterion(max_steps: usize) -> Or<MinsDiff, Stagnation> {
+    MinsDiff(1e-8).or(Stagnation(max_steps))
+}
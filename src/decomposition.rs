@@ -0,0 +1,154 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use crate::observables::Sublattices;
+use crate::System;
+
+// Pairs of elements at the same distance, up to `tolerance`, form one shell.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ShellEnergy {
+    pub distance: f64,
+    pub pairs: usize,
+    pub energy: f64,
+    // Pairs with positive energy, not satisfied by the state
+    pub frustrated: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecompositionConfig {
+    // Distances closer than this fall into one shell, 1e-3 nearest neighbour distances by default
+    pub tolerance: Option<f64>,
+    // Elements i / cell_size form one unit, 3 for trimer
+    pub cell_size: usize,
+}
+
+impl Default for DecompositionConfig {
+    fn default() -> Self {
+        Self { tolerance: None, cell_size: 3 }
+    }
+}
+
+// Energy of the state split into pair contributions s_i s_j M_ij, every pair counted once, so that
+// every part sums to energy().
+#[derive(Debug, Clone)]
+pub struct EnergyDecomposition {
+    pub total: f64,
+    pub shells: Vec<ShellEnergy>,
+    // Symmetric, the pairs between sublattices k and l are split between [k][l] and [l][k]
+    pub sublattices: Vec<Vec<f64>>,
+    pub within_cells: f64,
+    pub between_cells: f64,
+}
+
+impl EnergyDecomposition {
+    pub fn new(system: &System, sublattices: &Sublattices, config: &DecompositionConfig) -> Self {
+        let signs = system.system_signs();
+        let matrix = system.default_energy_matrix();
+        let nearest = system.nearest_distance();
+        let tolerance = config.tolerance.unwrap_or(1e-3 * nearest);
+        let cell_size = config.cell_size;
+        assert!(cell_size > 0, "Cell size must be positive");
+
+        let mut distances: Vec<f64> = system.element_neighbors()
+            .iter()
+            .enumerate()
+            .flat_map(|(i, n)| n.iter().filter(move |(j, _)| *j > i).map(|(_, d)| d.0))
+            .collect();
+        distances.sort_by(f64::total_cmp);
+        let mut shells: Vec<ShellEnergy> = Vec::new();
+        for d in distances {
+            if shells.last().is_none_or(|s| d - s.distance > tolerance) {
+                shells.push(ShellEnergy { distance: d, ..ShellEnergy::default() });
+            }
+        }
+
+        let count = sublattices.count();
+        let mut pairs = vec![vec![0.0; count]; count];
+        let mut within_cells = 0.0;
+        let mut between_cells = 0.0;
+        let mut total = 0.0;
+
+        for (i, neighbors) in system.element_neighbors().iter().enumerate() {
+            for (j, d) in neighbors.iter().filter(|(j, _)| *j > i) {
+                let energy = signs[i] * signs[*j] * matrix[(i, *j)];
+                total += energy;
+
+                let shell = shells.partition_point(|s| s.distance < d.0 - tolerance).min(shells.len() - 1);
+                let shell = &mut shells[shell];
+                shell.pairs += 1;
+                shell.energy += energy;
+                shell.frustrated += (energy > 0.0) as usize;
+
+                let (k, l) = (sublattices.label(i), sublattices.label(*j));
+                pairs[k][l] += energy / 2.0;
+                pairs[l][k] += energy / 2.0;
+
+                if i / cell_size == j / cell_size {
+                    within_cells += energy;
+                } else {
+                    between_cells += energy;
+                }
+            }
+        }
+
+        Self {
+            total,
+            shells,
+            sublattices: pairs,
+            within_cells,
+            between_cells,
+        }
+    }
+
+    // Shells holding at least `fraction` of the largest absolute shell energy.
+    pub fn dominant_shells(&self, fraction: f64) -> Vec<&ShellEnergy> {
+        let largest = self.shells.iter().map(|s| s.energy.abs()).fold(0.0, f64::max);
+        self.shells.iter().filter(|s| s.energy.abs() >= fraction * largest).collect()
+    }
+
+    pub fn write_shells_csv(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "distance,pairs,energy,frustrated")?;
+        for s in &self.shells {
+            writeln!(writer, "{},{},{},{}", s.distance, s.pairs, s.energy, s.frustrated)?;
+        }
+        writer.flush()
+    }
+
+    pub fn write_sublattices_csv(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "first,second,energy")?;
+        for (k, row) in self.sublattices.iter().enumerate() {
+            for (l, energy) in row.iter().enumerate().skip(k) {
+                let energy = if k == l { *energy } else { 2.0 * energy };
+                writeln!(writer, "{},{},{}", k, l, energy)?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+impl Display for EnergyDecomposition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Energy: {}", self.total)?;
+        writeln!(f, "Within cells: {}", self.within_cells)?;
+        writeln!(f, "Between cells: {}", self.between_cells)?;
+        writeln!(f, "Sublattice pairs:")?;
+        for (k, row) in self.sublattices.iter().enumerate() {
+            for (l, energy) in row.iter().enumerate().skip(k) {
+                let energy = if k == l { *energy } else { 2.0 * energy };
+                writeln!(f, "  {:>2}-{:<2} {:>14.6e}", k, l, energy)?;
+            }
+        }
+        write!(f, "Shells:")?;
+        for s in self.dominant_shells(1e-3) {
+            write!(
+                f,
+                "\n  r = {:<10.3} pairs {:>6}  frustrated {:>6}  energy {:>14.6e}",
+                s.distance, s.pairs, s.frustrated, s.energy,
+            )?;
+        }
+        Ok(())
+    }
+}
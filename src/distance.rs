@@ -0,0 +1,138 @@
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use bitvec::prelude::BitVec;
+use crate::symmetry::SpinSymmetry;
+use crate::utils::write_element_map;
+use crate::System;
+
+#[inline(always)]
+pub fn hamming(a: &BitVec, b: &BitVec) -> usize {
+    (a.clone() ^ b).count_ones()
+}
+
+// Spin overlap q = sum(s_i t_i) / N: 1 for equal states, -1 for globally flipped ones.
+pub fn overlap(a: &BitVec, b: &BitVec) -> f64 {
+    1.0 - 2.0 * hamming(a, b) as f64 / a.len().max(1) as f64
+}
+
+#[inline(always)]
+pub fn hamming_modulo_flip(a: &BitVec, b: &BitVec) -> usize {
+    let distance = hamming(a, b);
+    distance.min(a.len() - distance)
+}
+
+// Overlaps of every pair of states; the Edwards-Anderson parameter of replicas is the mean of
+// |q| over the pairs off the diagonal.
+pub fn overlap_matrix(states: &[BitVec]) -> Vec<Vec<f64>> {
+    states.iter().map(|a| states.iter().map(|b| overlap(a, b)).collect()).collect()
+}
+
+pub fn edwards_anderson(states: &[BitVec]) -> f64 {
+    let n = states.len();
+    if n < 2 {
+        return 1.0;
+    }
+
+    let mut sum = 0.0;
+    for i in 0..n {
+        for j in i + 1..n {
+            sum += overlap(&states[i], &states[j]).abs();
+        }
+    }
+    sum / (n * (n - 1) / 2) as f64
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alignment {
+    pub distance: usize,
+    // Index of the symmetry applied to the first state
+    pub symmetry: usize,
+    pub flipped: bool,
+    // First state after the symmetry and flip
+    pub aligned: BitVec,
+}
+
+// Smallest Hamming distance between the images of `a` under the symmetries, optionally flipped,
+// and `b`.
+pub fn align(a: &BitVec, b: &BitVec, symmetries: &[SpinSymmetry], global_flip: bool) -> Alignment {
+    let identity = [SpinSymmetry::identity(a.len())];
+    let symmetries = if symmetries.is_empty() { &identity[..] } else { symmetries };
+
+    let mut best: Option<Alignment> = None;
+    for (index, symmetry) in symmetries.iter().enumerate() {
+        let image = symmetry.apply(a);
+        let flips: &[bool] = if global_flip { &[false, true] } else { &[false] };
+        for flipped in flips {
+            let candidate = if *flipped { !image.clone() } else { image.clone() };
+            let distance = hamming(&candidate, b);
+            if best.as_ref().is_none_or(|x| distance < x.distance) {
+                best = Some(Alignment { distance, symmetry: index, flipped: *flipped, aligned: candidate });
+            }
+        }
+    }
+
+    best.unwrap()
+}
+
+// Overlap of the two states within `radius` of every element, 1 where they agree locally.
+pub fn local_overlap(system: &System, a: &BitVec, b: &BitVec, radius: f64) -> Vec<f64> {
+    (0..system.size())
+        .map(|i| {
+            let (same, total) = system
+                .neighbors(i, radius)
+                .fold((0, 0), |(same, total), (j, _)| (same + (a[j] == b[j]) as usize, total + 1));
+            2.0 * same as f64 / total.max(1) as f64 - 1.0
+        })
+        .collect()
+}
+
+// Elements of `system` in its state, red where `other` differs from it.
+pub fn write_difference_png(system: &System, other: &BitVec, path: impl AsRef<Path>, width: u32) -> anyhow::Result<()> {
+    let colors: Vec<_> = system.system_state()
+        .iter()
+        .zip(other.iter())
+        .map(|(a, b)| if *a == *b { (200, 200, 200) } else { (220, 0, 0) })
+        .collect();
+
+    write_element_map(path, system, &colors, &[], width)
+}
+
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub size: usize,
+    pub hamming: usize,
+    pub overlap: f64,
+    pub hamming_modulo_flip: usize,
+    pub symmetric: Alignment,
+    pub energies: (f64, f64),
+}
+
+impl Comparison {
+    pub fn new(a: &System, b: &System, symmetries: &[SpinSymmetry]) -> Self {
+        let (sa, sb) = (a.system_state(), b.system_state());
+        Self {
+            size: a.size(),
+            hamming: hamming(sa, sb),
+            overlap: overlap(sa, sb),
+            hamming_modulo_flip: hamming_modulo_flip(sa, sb),
+            symmetric: align(sa, sb, symmetries, true),
+            energies: (a.energy(), b.energy()),
+        }
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Energies: {} / {} (difference {})", self.energies.0, self.energies.1, self.energies.1 - self.energies.0)?;
+        writeln!(f, "Hamming distance: {} of {}", self.hamming, self.size)?;
+        writeln!(f, "Overlap: {}", self.overlap)?;
+        writeln!(f, "Distance modulo global flip: {}", self.hamming_modulo_flip)?;
+        write!(
+            f,
+            "Distance modulo symmetries: {} (symmetry {}{})",
+            self.symmetric.distance,
+            self.symmetric.symmetry,
+            if self.symmetric.flipped { ", flipped" } else { "" },
+        )
+    }
+}
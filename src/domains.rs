@@ -0,0 +1,273 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use bitvec::prelude::BitVec;
+use ordered_float::OrderedFloat;
+use crate::symmetry::{PositionLookup, SpinSymmetry};
+use crate::system::Vec2;
+use crate::utils::{write_element_map, Rgb};
+use crate::System;
+
+const PALETTE: [Rgb; 10] = [
+    (230, 25, 75),
+    (60, 180, 75),
+    (255, 225, 25),
+    (0, 130, 200),
+    (245, 130, 48),
+    (145, 30, 180),
+    (70, 240, 240),
+    (240, 50, 230),
+    (210, 245, 60),
+    (250, 190, 212),
+];
+
+// Ordered phase given by equivalent reference states: a ground state, its global flip, its
+// rotated and translated copies. Domains of one phase differ in the variant they match.
+#[derive(Debug, Clone)]
+pub struct Phase {
+    pub name: String,
+    pub variants: Vec<BitVec>,
+}
+
+impl Phase {
+    pub fn new(name: impl Into<String>, reference: BitVec) -> Self {
+        Self { name: name.into(), variants: vec![reference] }
+    }
+
+    fn push(&mut self, variant: BitVec) {
+        if !self.variants.contains(&variant) {
+            self.variants.push(variant);
+        }
+    }
+
+    pub fn with_global_flip(mut self) -> Self {
+        for variant in self.variants.clone() {
+            self.push(!variant);
+        }
+        self
+    }
+
+    pub fn with_symmetries(mut self, symmetries: &[SpinSymmetry]) -> Self {
+        for variant in self.variants.clone() {
+            for symmetry in symmetries {
+                self.push(symmetry.apply(&variant));
+            }
+        }
+        self
+    }
+
+    // Copies shifted by lattice vectors, see translate_state.
+    pub fn with_translations(mut self, system: &System, shifts: &[Vec2]) -> Self {
+        for variant in self.variants.clone() {
+            for shift in shifts {
+                self.push(translate_state(system, &variant, *shift));
+            }
+        }
+        self
+    }
+}
+
+// Element i takes the state of the element at its position minus `shift` with the same default
+// moment; elements without such a partner at the boundary keep their own state.
+pub fn translate_state(system: &System, state: &BitVec, shift: Vec2) -> BitVec {
+    let elements = system.elements();
+    let lookup = PositionLookup::new(system);
+
+    elements
+        .iter()
+        .enumerate()
+        .map(|(i, e)| {
+            let pos = e.pos.map(|x| x.0) - shift;
+            lookup
+                .at(pos)
+                .find(|j| elements[*j].magn == e.magn)
+                .map_or(state[i], |j| state[j])
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DomainConfig {
+    // Neighbourhood compared with the variants, 2.5 nearest neighbour distances by default
+    pub radius: Option<f64>,
+    // Elements closer than this are linked into domains and walls, 1.5 nearest neighbour
+    // distances by default so that neighbouring units of the lattice are linked too
+    pub link: Option<f64>,
+    // Smallest fraction of matching neighbours to belong to a variant, 0.9 when zero
+    pub threshold: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Domain {
+    pub variant: usize,
+    pub elements: Vec<usize>,
+}
+
+// Wall between two domains, or between a domain and disordered elements when one side is None.
+#[derive(Debug, Clone)]
+pub struct Wall {
+    pub between: (Option<usize>, Option<usize>),
+    // Linked pairs of elements crossing the wall
+    pub bonds: usize,
+    pub elements: usize,
+    // Energy above the matched variants of the elements at the wall; an element at several walls
+    // shares its excess between them
+    pub energy: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Domains {
+    // Domain of every element, None for disordered ones
+    pub labels: Vec<Option<usize>>,
+    // Variant best matching the neighbourhood of every element and the matching fraction
+    pub matches: Vec<(usize, f64)>,
+    pub domains: Vec<Domain>,
+    pub walls: Vec<Wall>,
+    // Energy of the state above the best matching variants, (row_energies - reference) / 2
+    pub excess_energy: f64,
+}
+
+impl Domains {
+    pub fn new(system: &System, phase: &Phase, config: &DomainConfig) -> Self {
+        let size = system.size();
+        let state = system.system_state();
+        let nearest = system.nearest_distance();
+        let radius = config.radius.unwrap_or(2.5 * nearest);
+        let link = config.link.unwrap_or(1.5 * nearest);
+        let threshold = if config.threshold > 0.0 { config.threshold } else { 0.9 };
+
+        let matches: Vec<(usize, f64)> = (0..size)
+            .map(|i| {
+                let neighbors: Vec<_> = system.neighbors(i, radius).map(|(j, _)| j).collect();
+                phase.variants
+                    .iter()
+                    .enumerate()
+                    .map(|(v, variant)| {
+                        let matching = neighbors.iter().filter(|j| state[**j] == variant[**j]).count();
+                        (v, matching as f64 / neighbors.len() as f64)
+                    })
+                    .max_by_key(|(v, score)| (OrderedFloat(*score), std::cmp::Reverse(*v)))
+                    .unwrap_or((0, 0.0))
+            })
+            .collect();
+
+        let links = system.links(link);
+
+        // Connected elements matching the same variant form a domain
+        let mut labels: Vec<Option<usize>> = vec![None; size];
+        let mut domains = Vec::new();
+        for start in 0..size {
+            let (variant, score) = matches[start];
+            if labels[start].is_some() || score < threshold {
+                continue;
+            }
+
+            let label = domains.len();
+            let mut members = vec![start];
+            labels[start] = Some(label);
+            let mut next = 0;
+            while next < members.len() {
+                for j in &links[members[next]] {
+                    if labels[*j].is_none() && matches[*j].0 == variant && matches[*j].1 >= threshold {
+                        labels[*j] = Some(label);
+                        members.push(*j);
+                    }
+                }
+                next += 1;
+            }
+
+            members.sort_unstable();
+            domains.push(Domain { variant, elements: members });
+        }
+
+        let mut references: HashMap<usize, System> = HashMap::new();
+        let excess: Vec<f64> = (0..size)
+            .map(|i| {
+                let variant = matches[i].0;
+                let reference = references.entry(variant).or_insert_with(|| {
+                    let mut reference = system.clone();
+                    reference.set_system_state(phase.variants[variant].clone());
+                    reference
+                });
+                (system.row_energies()[i] - reference.row_energies()[i]) / 2.0
+            })
+            .collect();
+
+        let mut walls: BTreeMap<(Option<usize>, Option<usize>), Wall> = BTreeMap::new();
+        let mut element_walls: Vec<Vec<(Option<usize>, Option<usize>)>> = vec![Vec::new(); size];
+        for i in 0..size {
+            for j in links[i].iter().filter(|j| **j > i) {
+                if labels[i] == labels[*j] {
+                    continue;
+                }
+
+                let key = (labels[i].min(labels[*j]), labels[i].max(labels[*j]));
+                walls.entry(key).or_insert(Wall { between: key, bonds: 0, elements: 0, energy: 0.0 }).bonds += 1;
+                for k in [i, *j] {
+                    if !element_walls[k].contains(&key) {
+                        element_walls[k].push(key);
+                    }
+                }
+            }
+        }
+
+        for (i, keys) in element_walls.iter().enumerate() {
+            for key in keys {
+                let wall = walls.get_mut(key).unwrap();
+                wall.elements += 1;
+                wall.energy += excess[i] / keys.len() as f64;
+            }
+        }
+
+        Self {
+            labels,
+            matches,
+            domains,
+            walls: walls.into_values().collect(),
+            excess_energy: excess.iter().sum(),
+        }
+    }
+
+    pub fn ordered_fraction(&self) -> f64 {
+        self.labels.iter().filter(|l| l.is_some()).count() as f64 / self.labels.len().max(1) as f64
+    }
+
+    pub fn largest_domain(&self) -> usize {
+        self.domains.iter().map(|d| d.elements.len()).max().unwrap_or(0)
+    }
+
+    // Domain of every element, -1 for disordered ones, as the extra column of load_mfsys files.
+    pub fn save_mfsys(&self, system: &System, filename: impl AsRef<Path>) {
+        let labels: Vec<i64> = self.labels.iter().map(|l| l.map_or(-1, |l| l as i64)).collect();
+        system.save_mfsys_labeled(filename, &labels);
+    }
+
+    // Elements colored by the variant of their domain, disordered elements black.
+    pub fn write_png(&self, system: &System, path: impl AsRef<Path>, width: u32) -> anyhow::Result<()> {
+        let colors: Vec<_> = self.labels
+            .iter()
+            .map(|l| l.map_or((0, 0, 0), |l| PALETTE[self.domains[l].variant % PALETTE.len()]))
+            .collect();
+
+        write_element_map(path, system, &colors, &[], width)
+    }
+}
+
+impl Display for Domains {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut variants = BTreeMap::new();
+        for domain in &self.domains {
+            *variants.entry(domain.variant).or_insert(0) += 1;
+        }
+
+        writeln!(f, "Domains: {}", self.domains.len())?;
+        for (variant, count) in variants {
+            writeln!(f, "  variant {:>3}: {} domains", variant, count)?;
+        }
+        writeln!(f, "Largest domain: {} elements", self.largest_domain())?;
+        writeln!(f, "Ordered: {:.2}%", 100.0 * self.ordered_fraction())?;
+        writeln!(f, "Wall bonds: {}", self.walls.iter().map(|w| w.bonds).sum::<usize>())?;
+        writeln!(f, "Wall energy: {}", self.walls.iter().map(|w| w.energy).sum::<f64>())?;
+        write!(f, "Excess energy: {}", self.excess_energy)
+    }
+}
@@ -0,0 +1,156 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use crate::perebor::perebor_degeneracy;
+use crate::thermo::ThermoReport;
+use crate::System;
+
+// Residual entropy per spin with its error, from exact counting or integration.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Entropy {
+    pub size: usize,
+    pub value: f64,
+    pub error: f64,
+}
+
+impl Display for Entropy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "N = {:<6} S/N = {:.6} +- {:.6}", self.size, self.value, self.error)
+    }
+}
+
+// ln of the number of ground states per spin. States closer to the ground state than
+// `tolerance` count as degenerate, 1e-9 of its magnitude by default; the accumulated drift of
+// the incremental energies is far below that.
+pub fn exact_entropy(system: &System, tolerance: Option<f64>) -> (Entropy, f64) {
+    let scale = system.row_energies().iter().map(|e| e.abs()).sum::<f64>() / 2.0;
+    let degeneracy = perebor_degeneracy(system, tolerance.unwrap_or(1e-9 * scale));
+    let entropy = Entropy {
+        size: system.size(),
+        value: (degeneracy.count as f64).ln() / system.size() as f64,
+        error: 0.0,
+    };
+    (entropy, degeneracy.energy)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct EntropyPoint {
+    pub temperature: f64,
+    pub entropy: f64,
+    pub error: f64,
+}
+
+// S(T)/N by thermodynamic integration of the per spin energies of annealing reports:
+// s(beta) = ln 2 + beta e(beta) - integral from 0 to beta of e(beta') d beta',
+// with the trapezoid rule in beta. Above the hottest temperature e is taken linear in beta as in
+// the high temperature expansion. Errors of the energies are treated as independent.
+pub fn integrate_entropy(reports: &[ThermoReport]) -> Vec<EntropyPoint> {
+    let mut points: Vec<(f64, f64, f64)> = reports
+        .iter()
+        .map(|r| (1.0 / r.temperature, r.energy.mean, r.energy.error))
+        .collect();
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let Some(&(first_beta, first_energy, _)) = points.first() else {
+        return Vec::new();
+    };
+
+    // Integral of e and the weight of every energy in it
+    let mut integral = first_beta * first_energy / 2.0;
+    let mut weights = vec![first_beta / 2.0];
+    let mut result = Vec::with_capacity(points.len());
+
+    for (k, &(beta, energy, error)) in points.iter().enumerate() {
+        if k > 0 {
+            let (previous_beta, previous_energy, _) = points[k - 1];
+            let step = beta - previous_beta;
+            integral += step * (energy + previous_energy) / 2.0;
+            weights[k - 1] += step / 2.0;
+            weights.push(step / 2.0);
+        }
+
+        let variance = weights[..k]
+            .iter()
+            .zip(&points)
+            .map(|(w, p)| (w * p.2).powi(2))
+            .sum::<f64>()
+            + ((beta - weights[k]) * error).powi(2);
+
+        result.push(EntropyPoint {
+            temperature: 1.0 / beta,
+            entropy: std::f64::consts::LN_2 + beta * energy - integral,
+            error: variance.sqrt(),
+        });
+    }
+
+    result.reverse();
+    result
+}
+
+// Linear fit S/N = s + a / N weighted by the errors, the intercept is the infinite size entropy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extrapolation {
+    pub entropy: f64,
+    pub error: f64,
+    pub slope: f64,
+}
+
+pub fn extrapolate(entropies: &[Entropy]) -> Option<Extrapolation> {
+    if entropies.len() < 2 {
+        return None;
+    }
+
+    // Exact values get the weight of the most precise integrated one
+    let smallest = entropies.iter().map(|e| e.error).filter(|e| *e > 0.0).fold(f64::INFINITY, f64::min);
+    let exact = !smallest.is_finite();
+    let smallest = if exact { 1.0 } else { smallest };
+
+    let (mut sw, mut sx, mut sy, mut sxx, mut sxy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for e in entropies {
+        let w = 1.0 / e.error.max(smallest).powi(2);
+        let x = 1.0 / e.size as f64;
+        sw += w;
+        sx += w * x;
+        sy += w * e.value;
+        sxx += w * x * x;
+        sxy += w * x * e.value;
+    }
+
+    let determinant = sw * sxx - sx * sx;
+    if determinant.abs() <= f64::EPSILON * sw * sxx {
+        return None;
+    }
+
+    let entropy = (sxx * sy - sx * sxy) / determinant;
+    let slope = (sw * sxy - sx * sy) / determinant;
+
+    // Without errors, as for exact counts only, the scatter around the fit sets the scale; two
+    // exact points leave the error unknown
+    let scale = if !exact {
+        1.0
+    } else if entropies.len() > 2 {
+        let residuals = entropies
+            .iter()
+            .map(|e| (e.value - entropy - slope / e.size as f64).powi(2))
+            .sum::<f64>();
+        residuals / (entropies.len() - 2) as f64
+    } else {
+        f64::NAN
+    };
+
+    Some(Extrapolation {
+        entropy,
+        error: (scale * sxx / determinant).sqrt(),
+        slope,
+    })
+}
+
+pub fn write_csv(points: &[EntropyPoint], path: impl AsRef<Path>) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "t,s,s_error")?;
+    for p in points {
+        writeln!(writer, "{},{},{}", p.temperature, p.entropy, p.error)?;
+    }
+    writer.flush()
+}
@@ -0,0 +1,225 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use crate::system::Vec2;
+use crate::top_k::Lowest;
+use crate::utils::{write_element_map, Rgb};
+use crate::System;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExcitationConfig {
+    // Cheapest pairs and clusters of every size kept
+    pub keep: usize,
+    // Largest connected cluster flipped together
+    pub cluster_size: usize,
+    // Elements closer than this are connected, 1.5 nearest neighbour distances by default
+    pub link: Option<f64>,
+    pub bins: usize,
+}
+
+impl Default for ExcitationConfig {
+    fn default() -> Self {
+        Self { keep: 20, cluster_size: 3, link: None, bins: 20 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Excitation {
+    pub spins: Vec<usize>,
+    pub energy: f64,
+}
+
+impl Excitation {
+    pub fn center(&self, system: &System) -> Vec2 {
+        let sum: Vec2 = self.spins.iter().map(|i| system.elements()[*i].pos.map(|x| x.0)).sum();
+        sum / self.spins.len() as f64
+    }
+}
+
+// Energies of the excitations above the current state, which should be a minimum: every single
+// flip, the cheapest flips of any two spins and the cheapest flips of connected clusters.
+#[derive(Debug, Clone)]
+pub struct ExcitationSpectrum {
+    // flip_delta of every element
+    pub single: Vec<f64>,
+    pub pairs: Vec<Excitation>,
+    // Cheapest connected clusters of sizes 2..=cluster_size, clusters[k] of size k + 2
+    pub clusters: Vec<Vec<Excitation>>,
+    bins: usize,
+}
+
+impl ExcitationSpectrum {
+    pub fn new(system: &System, config: &ExcitationConfig) -> Self {
+        let size = system.size();
+        let single: Vec<f64> = (0..size).map(|i| system.flip_delta(i)).collect();
+
+        let mut pairs = Lowest::new(config.keep);
+        for i in 0..size {
+            for j in i + 1..size {
+                push(&mut pairs, &[i, j], system.pair_flip_delta(i, j));
+            }
+        }
+
+        let link = config.link.unwrap_or(1.5 * system.nearest_distance());
+        let links = system.links(link);
+
+        let mut clusters: Vec<Lowest<Excitation>> = (2..=config.cluster_size).map(|_| Lowest::new(config.keep)).collect();
+        if !clusters.is_empty() {
+            for start in 0..size {
+                let extension: Vec<usize> = links[start].iter().copied().filter(|j| *j > start).collect();
+                extend_clusters(system, &links, &mut vec![start], single[start], extension, &mut clusters);
+            }
+        }
+
+        Self {
+            single,
+            pairs: pairs.into_vec(),
+            clusters: clusters.into_iter().map(Lowest::into_vec).collect(),
+            bins: config.bins.max(1),
+        }
+    }
+
+    // Cheapest single flip; negative when the state is not a local minimum.
+    pub fn gap(&self) -> f64 {
+        self.single.iter().copied().fold(f64::INFINITY, f64::min)
+    }
+
+    pub fn unstable(&self) -> usize {
+        self.single.iter().filter(|d| **d < 0.0).count()
+    }
+
+    // Single flip energies in equal bins between the cheapest and the most expensive one, as the
+    // lower bound of every bin and its count.
+    pub fn histogram(&self) -> Vec<(f64, usize)> {
+        let min = self.gap();
+        let max = self.single.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        if !min.is_finite() {
+            return Vec::new();
+        }
+
+        let width = if max > min { (max - min) / self.bins as f64 } else { 1.0 };
+        let mut counts = vec![0; self.bins];
+        for delta in &self.single {
+            counts[(((delta - min) / width) as usize).min(self.bins - 1)] += 1;
+        }
+
+        counts.into_iter().enumerate().map(|(k, c)| (min + k as f64 * width, c)).collect()
+    }
+
+    pub fn write_single_csv(&self, system: &System, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "element,x,y,delta")?;
+        for (i, (e, delta)) in system.elements().iter().zip(&self.single).enumerate() {
+            writeln!(writer, "{},{},{},{}", i, e.pos.x, e.pos.y, delta)?;
+        }
+        writer.flush()
+    }
+
+    // Pairs and clusters with their spins separated by spaces.
+    pub fn write_clusters_csv(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(writer, "kind,size,energy,spins")?;
+        let rows = self.pairs.iter().map(|e| ("pair", e)).chain(self.clusters.iter().flatten().map(|e| ("cluster", e)));
+        for (kind, e) in rows {
+            let spins: Vec<String> = e.spins.iter().map(|s| s.to_string()).collect();
+            writeln!(writer, "{},{},{},{}", kind, e.spins.len(), e.energy, spins.join(" "))?;
+        }
+        writer.flush()
+    }
+
+    // Elements colored by their single flip energy from white for the cheapest to dark blue,
+    // unstable ones red; the centers of the cheapest cluster of every size marked orange.
+    pub fn write_png(&self, system: &System, path: impl AsRef<Path>, width: u32) -> anyhow::Result<()> {
+        let min = self.gap().max(0.0);
+        let max = self.single.iter().copied().fold(min, f64::max);
+        let range = if max > min { max - min } else { 1.0 };
+
+        let colors: Vec<Rgb> = self.single
+            .iter()
+            .map(|delta| {
+                if *delta < 0.0 {
+                    return (220, 0, 0);
+                }
+                let t = (delta - min) / range;
+                ((255.0 * (1.0 - t)) as u8, (255.0 * (1.0 - 0.8 * t)) as u8, (255.0 - 100.0 * t) as u8)
+            })
+            .collect();
+        let markers: Vec<_> = self.clusters
+            .iter()
+            .filter_map(|c| c.first())
+            .map(|e| (e.center(system), (255, 140, 0)))
+            .collect();
+
+        write_element_map(path, system, &colors, &markers, width)
+    }
+}
+
+// Checks the threshold first, so that the spins are only copied for the excitations kept.
+fn push(lowest: &mut Lowest<Excitation>, spins: &[usize], energy: f64) {
+    if energy < lowest.threshold() {
+        lowest.push(energy, Excitation { spins: spins.to_vec(), energy });
+    }
+}
+
+// Connected clusters containing cluster[0] as their smallest element, each found once (ESU,
+// Wernicke 2006). Every added spin updates the energy as in flips_delta.
+fn extend_clusters(
+    system: &System,
+    links: &[Vec<usize>],
+    cluster: &mut Vec<usize>,
+    energy: f64,
+    extension: Vec<usize>,
+    lowest: &mut [Lowest<Excitation>],
+) {
+    if cluster.len() > lowest.len() {
+        return;
+    }
+
+    let start = cluster[0];
+    let signs = system.system_signs();
+    let matrix = system.default_energy_matrix();
+    let mut extension = extension;
+
+    while let Some(w) = extension.pop() {
+        let mut next = extension.clone();
+        for u in &links[w] {
+            let exclusive = *u > start
+                && !cluster.contains(u)
+                && !next.contains(u)
+                && cluster.iter().all(|c| !links[*c].contains(u));
+            if exclusive {
+                next.push(*u);
+            }
+        }
+
+        let coupling: f64 = cluster.iter().map(|i| matrix[(*i, w)] * signs[*i]).sum();
+        let energy = energy + system.flip_delta(w) + 4.0 * coupling * signs[w];
+
+        cluster.push(w);
+        push(&mut lowest[cluster.len() - 2], cluster, energy);
+        extend_clusters(system, links, cluster, energy, next, lowest);
+        cluster.pop();
+    }
+}
+
+impl Display for ExcitationSpectrum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Single flips: gap {:.6e}, unstable {}", self.gap(), self.unstable())?;
+        let histogram = self.histogram();
+        let largest = histogram.iter().map(|(_, c)| *c).max().unwrap_or(0).max(1);
+        for (lower, count) in &histogram {
+            writeln!(f, "  {:>14.6e} {:>6} {}", lower, count, "#".repeat(40 * count / largest))?;
+        }
+
+        if let Some(pair) = self.pairs.first() {
+            writeln!(f, "Cheapest pair: {:.6e} {:?}", pair.energy, pair.spins)?;
+        }
+        for cluster in &self.clusters {
+            if let Some(e) = cluster.first() {
+                writeln!(f, "Cheapest cluster of {}: {:.6e} {:?}", e.spins.len(), e.energy, e.spins)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -5,7 +5,26 @@ pub mod system;
 pub mod utils;
 pub mod runner;
 pub mod metropolis;
+pub mod tabu;
+pub mod portfolio;
 pub mod matrix;
+pub mod cancel;
+pub mod cluster_cache;
+pub mod clusters;
+pub mod criteria;
+pub mod symmetry;
+pub mod top_k;
+pub mod trajectory;
+pub mod observables;
+pub mod correlation;
+pub mod vertices;
+pub mod charges;
+pub mod domains;
+pub mod thermo;
+pub mod distance;
+pub mod decomposition;
+pub mod entropy;
+pub mod excitations;
 
 use bitvec::prelude::BitVec;
 use element::Element;
@@ -23,8 +42,13 @@ use rand::{Rng, thread_rng};
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
 use crate::perebor::perebor_states;
 use crate::runner::{State, StateRegisterer};
-use crate::utils::get_part_from_system;
+use crate::cancel::CancellationToken;
+use crate::cluster_cache::ClusterCache;
+use crate::clusters::{canonical_cluster, ClusterConfig, ClusterMapping, ClusterPlan};
 
+// Steepest descent with a linear search for the best flip. The energy matrix is dense, so every
+// flip changes all row energies: an index of the rows would need N updates per flip on top of the
+// O(N) reverse_spin, which the O(N) search already matches.
 pub fn greedy(system: &mut System, registerer: &impl StateRegisterer) {
     while let Some((index, _)) = system.row_energies().iter().copied()
         .enumerate()
@@ -36,6 +60,35 @@ pub fn greedy(system: &mut System, registerer: &impl StateRegisterer) {
     }
 }
 
+pub fn greedy_first_improvement(system: &mut System, registerer: &impl StateRegisterer) {
+    let size = system.size();
+    let mut start = 0;
+
+    while let Some(index) = (start..size).chain(0..start).find(|i| system.row_energies()[*i] > 0.0) {
+        system.reverse_spin(index);
+        registerer.register(system);
+        start = (index + 1) % size;
+    }
+}
+
+pub fn greedy_random_improvement(system: &mut System, registerer: &impl StateRegisterer) {
+    let mut rng = thread_rng();
+
+    loop {
+        let improving: Vec<_> = (0..system.size())
+            .filter(|i| system.row_energies()[*i] > 0.0)
+            .collect();
+
+        let index = match improving.choose(&mut rng) {
+            Some(index) => *index,
+            None => break,
+        };
+
+        system.reverse_spin(index);
+        registerer.register(system);
+    }
+}
+
 pub fn gibrid(system: &mut System, registerer: &impl StateRegisterer) {
     let system_size = system.size();
 
@@ -67,116 +120,205 @@ pub fn gibrid(system: &mut System, registerer: &impl StateRegisterer) {
     }
 }
 
+fn cluster_states(part: &[Element]) -> Vec<State> {
+    let system = System::new(part.to_vec());
+    let states = perebor_states(&system);
+
+    let min = states
+        .iter()
+        .min_by_key(|(s, _)| OrderedFloat(s.energy))
+        .map(|(s, _)| s.energy)
+        .unwrap();
+    let diff = min.abs() * 2.0 * 0.2;
+
+    states
+        .into_par_iter()
+        .map(|states| {
+            let mut v = Vec::with_capacity(2usize.pow(20));
+            v.extend(
+                states
+                    .1
+                    .into_iter()
+                    .filter(|state| (state.energy - min).abs() <= diff),
+            );
+            v
+        })
+        .reduce(Vec::new, |gv, v| gv.tap_mut(|gv| gv.extend(v)))
+}
+
 fn get_states(
     system: &System,
+    clusters: &[Vec<usize>],
     radius: f64,
+    symmetric: bool,
+    mut cache: Option<&mut ClusterCache>,
 ) -> (
     HashMap<Vec<Element>, Vec<State>>,
     HashMap<usize, Vec<Element>>,
+    Vec<ClusterMapping>,
 ) {
     let mut states_map = HashMap::new();
     let mut identity_map = HashMap::new();
+    let mut mappings = Vec::with_capacity(clusters.len());
 
-    for i in 0..system.size() {
-        let neighbors: Vec<_> = system.neighbors2(i, radius).map(|(i, _)| i).collect();
-
-
-        let part = get_part_from_system(system, &neighbors);
+    for (i, cluster) in clusters.iter().enumerate() {
+        let (part, mapping) = canonical_cluster(system, cluster, symmetric);
+        mappings.push(mapping);
         if !states_map.contains_key(&part) {
-            let system = System::new(part.clone());
-            let states = perebor_states(&system);
-
-            let min = states
-                .iter()
-                .min_by_key(|(s, _)| OrderedFloat(s.energy))
-                .map(|(s, _)| s.energy)
-                .unwrap();
-            let diff = min.abs() * 2.0 * 0.2;
-
-            let states: Vec<_> = states
-                .into_par_iter()
-                .map(|states| {
-                    let mut v = Vec::with_capacity(2usize.pow(20));
-                    v.extend(
-                        states
-                            .1
-                            .into_iter()
-                            .filter(|state| (state.energy - min).abs() <= diff),
-                    );
-                    v
-                })
-                .reduce(Vec::new, |gv, v| gv.tap_mut(|gv| gv.extend(v)));
+            let states = match cache.as_mut().and_then(|c| c.load(&part, radius)) {
+                Some(states) => states,
+                None => {
+                    let states = cluster_states(&part);
+                    if let Some(cache) = cache.as_mut() {
+                        cache.store(&part, radius, &states);
+                    }
+                    states
+                }
+            };
             states_map.insert(part.clone(), states);
         }
         identity_map.insert(i, part);
     }
 
-    (states_map, identity_map)
+    (states_map, identity_map, mappings)
 }
 
 
 pub struct GibridState {
     pub states_map: HashMap<Vec<Element>, Vec<State>>,
     pub identity_map: HashMap<usize, Vec<Element>>,
+    pub mappings: Vec<ClusterMapping>,
     pub radius: f64,
 }
 
-pub fn prepare_state(system: &System) -> GibridState {
-    let mut min = usize::MAX;
-    let mut max = usize::MIN;
-
-    let mut radius = 0.0;
-    let rnd_count = 19;
+impl GibridState {
+    #[inline(always)]
+    pub fn cluster_states(&self, element: usize) -> &[State] {
+        &self.states_map[&self.identity_map[&element]]
+    }
 
-    let max_radius = system.max_radius();
+    // Energy of the system with each of the cluster states applied, up to a constant shared by all
+    // of them: inner energy of the state plus its interaction with the spins outside the cluster.
+    pub fn context_energies(&self, system: &System, element: usize) -> Vec<f64> {
+        let ClusterMapping { order, inversion } = &self.mappings[element];
+        let matrix = system.default_energy_matrix();
+        let signs = system.system_signs();
+
+        let fields: Vec<f64> = order
+            .iter()
+            .map(|&e| {
+                let total = system.row_energies()[e] * signs[e];
+                let inside: f64 = order.iter().map(|&l| matrix[(e, l)] * signs[l]).sum();
+                total - inside
+            })
+            .collect();
+
+        self.cluster_states(element)
+            .iter()
+            .map(|state| {
+                state.energy + state.state
+                    .iter()
+                    .enumerate()
+                    .map(|(k, s)| if *s ^ inversion[k] { -fields[k] } else { fields[k] })
+                    .sum::<f64>()
+            })
+            .collect()
+    }
 
-    while max < rnd_count && radius < max_radius {
-        for i in 0..system.size() {
-            let count = system.neighbors(i, radius).count();
-            min = min.min(count);
-            max = dbg!(max.max(count));
-        }
-        radius += 10.0;
+    pub fn apply_state(&self, system: &mut System, element: usize, state: &State) {
+        let ClusterMapping { order, inversion } = &self.mappings[element];
+        system.set_spins(
+            state.state
+                .iter()
+                .enumerate()
+                .map(|(k, s)| (order[k], *s ^ inversion[k])),
+        );
     }
+}
+
+pub fn prepare_state(system: &System) -> GibridState {
+    prepare_state_with(system, &ClusterConfig::default(), None)
+}
 
-    dbg!(radius);
-    dbg!(max);
+pub fn prepare_state_with(system: &System, config: &ClusterConfig, cache: Option<&mut ClusterCache>) -> GibridState {
+    prepare_state_from_plan(system, ClusterPlan::new(system, config), cache)
+}
 
-    let (states_map, identity_map) = get_states(&system, radius);
+pub fn prepare_state_from_plan(system: &System, plan: ClusterPlan, cache: Option<&mut ClusterCache>) -> GibridState {
+    let ClusterPlan { clusters, radius, symmetric, .. } = plan;
+    let (states_map, identity_map, mappings) = get_states(system, &clusters, radius, symmetric, cache);
 
-    GibridState { states_map, identity_map, radius }
+    GibridState { states_map, identity_map, mappings, radius }
 }
 
-pub fn gibrid2(system: &mut System, registerer: &impl StateRegisterer, state: &GibridState) {
-    let GibridState { states_map, identity_map, radius } = state;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClusterMove {
+    // Any of the low-energy cluster states, ignoring the spins around the cluster
+    Uniform,
+    // Cluster state sampled by Boltzmann weight of its energy in the current surrounding. The
+    // temperature must not be negative or NaN, zero is the same as Best
+    HeatBath(f64),
+    // Cluster state with the lowest energy in the current surrounding
+    Best,
+}
 
-    let size = system.size();
-    let size10 = size / 10;
+pub fn gibrid2(system: &mut System, registerer: &impl StateRegisterer, state: &GibridState, cancel: &CancellationToken) {
+    gibrid2_with(system, registerer, state, ClusterMove::Uniform, cancel)
+}
 
-    let mut rng = thread_rng();
-    for step in 0..system.size() {
+pub fn gibrid2_with(
+    system: &mut System,
+    registerer: &impl StateRegisterer,
+    state: &GibridState,
+    cluster_move: ClusterMove,
+    cancel: &CancellationToken,
+) {
+    let cluster_move = match cluster_move {
+        ClusterMove::HeatBath(0.0) => ClusterMove::Best,
+        ClusterMove::HeatBath(temp) => {
+            assert!(temp > 0.0, "Heat-bath temperature must be positive, got {}", temp);
+            cluster_move
+        }
+        cluster_move => cluster_move,
+    };
 
-        if (step + 1) % size10 == 0 {
-            println!("Step {} / {}", step + 1, size);
+    let mut rng = thread_rng();
+    for _ in 0..system.size() {
+        if cancel.is_cancelled() {
+            break;
         }
 
         let random = rng.gen_range(0..system.size());
-        let cluster: Vec<_> = system.neighbors(random, *radius).map(|(i, _)| i).collect();
-
-        let identity = &identity_map[&random];
-        let states = &states_map[identity];
+        let states = state.cluster_states(random);
 
         if states.is_empty() {
             continue;
         }
 
-        system.set_spins(
-            states[rng.gen_range(0..states.len())]
-                .state
-                .iter()
+        let index = match cluster_move {
+            ClusterMove::Uniform => rng.gen_range(0..states.len()),
+            ClusterMove::Best => state.context_energies(system, random)
+                .into_iter()
                 .enumerate()
-                .map(|(i, s)| (cluster[i], *s)),
-        );
+                .min_by_key(|(_, e)| OrderedFloat(*e))
+                .unwrap()
+                .0,
+            ClusterMove::HeatBath(temp) => {
+                let energies = state.context_energies(system, random);
+                let min = energies.iter().copied().fold(f64::MAX, f64::min);
+                let weights: Vec<_> = energies.iter().map(|e| (-(e - min) / temp).exp()).collect();
+                let mut threshold = rng.gen::<f64>() * weights.iter().sum::<f64>();
+                weights
+                    .iter()
+                    .position(|w| {
+                        threshold -= w;
+                        threshold <= 0.0
+                    })
+                    .unwrap_or(weights.len() - 1)
+            }
+        };
+
+        state.apply_state(system, random, &states[index]);
         registerer.register(system);
         gibrid(system, registerer);
     }
@@ -2,25 +2,30 @@ use rand::{Rng, thread_rng};
 use crate::{StateRegisterer, System};
 
 pub fn metropolis_mc_step(system: &mut System, registerer: &impl StateRegisterer, temp: f64, steps: usize) {
+    metropolis_mc_step_observed(system, registerer, temp, steps, |_, _| {});
+}
+
+// Calls on_flip with the system and the spin after every accepted flip, so observables can follow it.
+pub fn metropolis_mc_step_observed<F: FnMut(&System, usize)>(
+    system: &mut System,
+    registerer: &impl StateRegisterer,
+    temp: f64,
+    steps: usize,
+    mut on_flip: F,
+) {
     let mut rng = thread_rng();
     let size = system.size();
 
     registerer.register(system);
 
-    for step in 0..steps {
-        let e1= system.energy();
+    for _ in 0..steps {
         let spin = rng.gen_range(0..size);
+        let delta = system.flip_delta(spin);
 
-        system.reverse_spin(spin);
-        registerer.register(system);
-
-        let e2 = system.energy();
-
-        if e2 >= e1 {
-            let p = ((e2-e1) / temp).exp();
-            if p < rng.gen() {
-                system.reverse_spin(spin);
-            }
+        if delta <= 0.0 || rng.gen::<f64>() < (-delta / temp).exp() {
+            system.reverse_spin(spin);
+            registerer.register(system);
+            on_flip(system, spin);
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use bitvec::prelude::BitVec;
+use ordered_float::OrderedFloat;
+use crate::system::Vec2;
+use crate::System;
+
+// Sum of the signed moments of all elements.
+pub fn magnetization(system: &System) -> Vec2 {
+    system.elements()
+        .iter()
+        .zip(system.system_signs())
+        .map(|(e, s)| e.magn() * *s)
+        .sum()
+}
+
+// Element i belongs to sublattice labels[i].
+#[derive(Debug, Clone)]
+pub struct Sublattices {
+    labels: Vec<usize>,
+    count: usize,
+}
+
+impl Sublattices {
+    pub fn new(labels: Vec<usize>) -> Self {
+        let count = labels.iter().max().map_or(0, |l| l + 1);
+        Self { labels, count }
+    }
+
+    // Element k of every cell of cell_size consecutive elements forms sublattice k. This matches the
+    // sublattices of trimer with cell size 3; cairo and wtf change the element order from cell to
+    // cell, use from_directions for them.
+    pub fn from_cells(system: &System, cell_size: usize) -> anyhow::Result<Self> {
+        anyhow::ensure!(cell_size > 0, "Cell size must be positive");
+        Ok(Self::new((0..system.size()).map(|i| i % cell_size).collect()))
+    }
+
+    // Elements with parallel default moments form one sublattice, numbered in order of appearance.
+    pub fn from_directions(system: &System) -> Self {
+        let mut directions = BTreeMap::new();
+        let labels = system.elements()
+            .iter()
+            .map(|e| {
+                let m = e.magn().normalized();
+                let key = [OrderedFloat((m.x * 1e6).round() + 0.0), OrderedFloat((m.y * 1e6).round() + 0.0)];
+                let next = directions.len();
+                *directions.entry(key).or_insert(next)
+            })
+            .collect();
+
+        Self::new(labels)
+    }
+
+    #[inline(always)]
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    #[inline(always)]
+    pub fn label(&self, element: usize) -> usize {
+        self.labels[element]
+    }
+
+    #[inline(always)]
+    pub fn labels(&self) -> &[usize] {
+        &self.labels
+    }
+}
+
+// Scalar order parameter sum(w_i * s_i) / sum(|w_i|): 1 in the pattern it describes, -1 in its
+// global flip and near 0 in a disordered state.
+#[derive(Debug, Clone)]
+pub struct OrderParameter {
+    pub name: String,
+    weights: Vec<f64>,
+    norm: f64,
+}
+
+impl OrderParameter {
+    pub fn new(name: impl Into<String>, weights: Vec<f64>) -> Self {
+        let norm = weights.iter().map(|w| w.abs()).sum::<f64>().max(f64::MIN_POSITIVE);
+        Self { name: name.into(), weights, norm }
+    }
+
+    // Mattis order parameter: overlap with an ordered reference state, such as a ground state.
+    pub fn from_state(name: impl Into<String>, reference: &BitVec) -> Self {
+        Self::new(name, reference.iter().map(|b| if *b { -1.0 } else { 1.0 }).collect())
+    }
+
+    // Staggered order parameter: every sublattice enters with its own sign.
+    pub fn staggered(name: impl Into<String>, sublattices: &Sublattices, signs: &[f64]) -> Self {
+        Self::new(name, sublattices.labels().iter().map(|l| signs[*l]).collect())
+    }
+
+    pub fn value(&self, system: &System) -> f64 {
+        self.sum(system) / self.norm
+    }
+
+    fn sum(&self, system: &System) -> f64 {
+        self.weights.iter().zip(system.system_signs()).map(|(w, s)| w * s).sum()
+    }
+}
+
+// Magnetization of the whole system and of every sublattice together with the order parameters,
+// kept up to date flip by flip.
+#[derive(Debug, Clone)]
+pub struct Magnetization {
+    sublattices: Sublattices,
+    order_parameters: Vec<OrderParameter>,
+    total: Vec2,
+    per_sublattice: Vec<Vec2>,
+    order_sums: Vec<f64>,
+}
+
+impl Magnetization {
+    pub fn new(system: &System, sublattices: Sublattices, order_parameters: Vec<OrderParameter>) -> Self {
+        let count = sublattices.count();
+        let mut magnetization = Self {
+            sublattices,
+            order_sums: vec![0.0; order_parameters.len()],
+            order_parameters,
+            total: Vec2::zero(),
+            per_sublattice: vec![Vec2::zero(); count],
+        };
+        magnetization.recalculate(system);
+        magnetization
+    }
+
+    pub fn recalculate(&mut self, system: &System) {
+        self.per_sublattice.iter_mut().for_each(|m| *m = Vec2::zero());
+        for (i, (e, s)) in system.elements().iter().zip(system.system_signs()).enumerate() {
+            self.per_sublattice[self.sublattices.label(i)] += e.magn() * *s;
+        }
+
+        self.total = self.per_sublattice.iter().copied().sum();
+        for (sum, parameter) in self.order_sums.iter_mut().zip(&self.order_parameters) {
+            *sum = parameter.sum(system);
+        }
+    }
+
+    // Must be called after system.reverse_spin(spin).
+    #[inline(always)]
+    pub fn update(&mut self, system: &System, spin: usize) {
+        let sign = system.system_signs()[spin];
+        let delta = system.elements()[spin].magn() * (2.0 * sign);
+
+        self.total += delta;
+        self.per_sublattice[self.sublattices.label(spin)] += delta;
+        for (sum, parameter) in self.order_sums.iter_mut().zip(&self.order_parameters) {
+            *sum += 2.0 * sign * parameter.weights[spin];
+        }
+    }
+
+    #[inline(always)]
+    pub fn total(&self) -> Vec2 {
+        self.total
+    }
+
+    #[inline(always)]
+    pub fn sublattices(&self) -> &Sublattices {
+        &self.sublattices
+    }
+
+    #[inline(always)]
+    pub fn sublattice(&self, label: usize) -> Vec2 {
+        self.per_sublattice[label]
+    }
+
+    #[inline(always)]
+    pub fn order_parameters(&self) -> &[OrderParameter] {
+        &self.order_parameters
+    }
+
+    pub fn order_parameter(&self, index: usize) -> f64 {
+        self.order_sums[index] / self.order_parameters[index].norm
+    }
+}
@@ -5,6 +5,7 @@ use ordered_float::OrderedFloat;
 use rayon::prelude::{ParallelBridge, ParallelIterator};
 use crate::runner::{State, StateRegistererInner};
 use crate::utils::grey_bitvec;
+use crate::cancel::CancellationToken;
 
 pub struct StateSaver {
     pub minimal_state: State,
@@ -51,7 +52,7 @@ impl StateSaver {
     }
 }
 
-pub fn perebor_cluster(system: &System, cluster: &[usize]) -> State {
+pub fn perebor_cluster(system: &System, cluster: &[usize], cancel: &CancellationToken) -> State {
     let thread_count = rayon::current_num_threads();
     let state_count = 2usize.pow(cluster.len() as u32);
     let block_size = state_count / thread_count;
@@ -86,6 +87,10 @@ pub fn perebor_cluster(system: &System, cluster: &[usize]) -> State {
             registerer.register(&system);
 
             for i in r.skip(1) {
+                if i & 0xfff == 0 && cancel.is_cancelled() {
+                    break;
+                }
+
                 let index = i.trailing_zeros();
                 system.reverse_spin(cluster[index as usize]);
                 registerer.register(&system);
@@ -197,3 +202,76 @@ fn perebor(system: &System, cluster: &[usize]) -> State {
         .min_by_key(|state| OrderedFloat(state.energy))
         .unwrap()
 }
+
+// Ground state energy and the number of states within `tolerance` above it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Degeneracy {
+    pub energy: f64,
+    pub count: usize,
+    // Energies of the counted states
+    energies: Vec<f64>,
+}
+
+impl Degeneracy {
+    fn new() -> Self {
+        Self { energy: f64::INFINITY, count: 0, energies: Vec::new() }
+    }
+
+    fn save(&mut self, energy: f64, tolerance: f64) {
+        if energy > self.energy + tolerance {
+            return;
+        }
+
+        if energy < self.energy {
+            self.energy = energy;
+            self.energies.retain(|e| *e <= energy + tolerance);
+        }
+        self.energies.push(energy);
+        self.count = self.energies.len();
+    }
+
+    fn merged(mut self, rhs: Self, tolerance: f64) -> Self {
+        for energy in rhs.energies {
+            self.save(energy, tolerance);
+        }
+        self
+    }
+}
+
+// Exhaustive count of the ground states, 2^size states in Gray code order like perebor_states.
+pub fn perebor_degeneracy(system: &System, tolerance: f64) -> Degeneracy {
+    let system_size = system.size();
+    let thread_count = rayon::current_num_threads();
+    let state_count = 2usize.pow(system_size as u32);
+    let block_size = state_count / thread_count;
+    let remain = state_count % thread_count;
+
+    let ranges = (0..thread_count).map(|i| {
+        let start = i * block_size + i.min(remain);
+        let count = block_size + if i < remain { 1 } else { 0 };
+        start..start + count
+    });
+
+    ranges
+        .into_iter()
+        .filter(|r| !r.is_empty())
+        .par_bridge()
+        .map(move |r| {
+            let mut system = system.clone();
+            let mut degeneracy = Degeneracy::new();
+            let bit_view = r.start
+                .view_bits::<Lsb0>()
+                .into_iter()
+                .take(system_size)
+                .collect();
+            system.set_system_state(grey_bitvec(bit_view));
+            degeneracy.save(system.energy(), tolerance);
+
+            for i in r.skip(1) {
+                system.reverse_spin(i.trailing_zeros() as usize);
+                degeneracy.save(system.energy(), tolerance);
+            }
+            degeneracy
+        })
+        .reduce(Degeneracy::new, |a, b| a.merged(b, tolerance))
+}
@@ -0,0 +1,185 @@
+use std::cell::Cell;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use ordered_float::OrderedFloat;
+use rayon::prelude::{IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use crate::cancel::CancellationToken;
+use crate::criteria::{RunProgress, StopCriterion, StopReason};
+use crate::metropolis::metropolis_mc_step;
+use crate::runner::{MutexStateRegisterer, RunObserver, RunReport, State, StateRegisterer, StateRegistererInner};
+use crate::tabu::tabu_search;
+use crate::{gibrid, gibrid2, greedy, GibridState, System};
+
+pub type PortfolioAlgorithm<'a> = Box<dyn Fn(&mut System, &dyn StateRegisterer, usize) + Sync + Send + 'a>;
+
+pub struct PortfolioWorker<'a> {
+    pub name: String,
+    pub algorithm: PortfolioAlgorithm<'a>,
+}
+
+impl<'a> PortfolioWorker<'a> {
+    pub fn new(name: impl Into<String>, algorithm: impl Fn(&mut System, &dyn StateRegisterer, usize) + Sync + Send + 'a) -> Self {
+        Self {
+            name: name.into(),
+            algorithm: Box::new(algorithm),
+        }
+    }
+
+    pub fn greedy() -> Self {
+        Self::new("greedy", |system, registerer, _| greedy(system, &registerer))
+    }
+
+    pub fn gibrid() -> Self {
+        Self::new("gibrid", |system, registerer, _| gibrid(system, &registerer))
+    }
+
+    pub fn gibrid2(state: &'a GibridState, cancel: &'a CancellationToken) -> Self {
+        Self::new("gibrid2", move |system, registerer, _| gibrid2(system, &registerer, state, cancel))
+    }
+
+    // Temperature is taken from the schedule by step index, so the worker can anneal.
+    pub fn metropolis(temp: impl Fn(usize) -> f64 + Sync + Send + 'a, steps: usize) -> Self {
+        Self::new("metropolis", move |system, registerer, step| {
+            metropolis_mc_step(system, &registerer, temp(step), steps)
+        })
+    }
+
+    pub fn tabu(tenure: usize, steps: usize) -> Self {
+        Self::new("tabu", move |system, registerer, _| tabu_search(system, &registerer, tenure, steps))
+    }
+}
+
+struct WorkerRegisterer<'a> {
+    shared: &'a MutexStateRegisterer,
+    improvements: &'a AtomicUsize,
+    best: Cell<f64>,
+}
+
+impl<'a> StateRegisterer for WorkerRegisterer<'a> {
+    fn register(&self, system: &System) {
+        self.best.set(self.best.get().min(system.energy()));
+        let mut inner = self.shared.0.lock().unwrap();
+        let before = inner.minimal_energy();
+        inner.register(system);
+        if inner.minimal_energy() != before {
+            self.improvements.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn minimal_state(&self) -> Option<State> {
+        self.shared.minimal_state()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkerContribution {
+    pub name: String,
+    pub improvements: usize,
+    pub best_energy: f64,
+    pub time: Duration,
+    pub migrations_received: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct PortfolioReport {
+    pub report: RunReport,
+    pub contributions: Vec<WorkerContribution>,
+}
+
+// Every `migration_interval` steps the workers ending the step above the median energy
+// continue from the global minimal state. Zero disables migration.
+pub fn runner_portfolio<C: StopCriterion, O: RunObserver>(
+    system: System,
+    workers: &[PortfolioWorker],
+    mut criterion: C,
+    cancel: &CancellationToken,
+    migration_interval: usize,
+    mut observer: O,
+) -> PortfolioReport {
+    let state_register = MutexStateRegisterer(Mutex::new(StateRegistererInner::new()));
+    let mut progress = RunProgress::new();
+    let start_flips = system.flip_count() * workers.len() as u64;
+
+    // The starting state is the result of a run stopped before anything else is registered
+    state_register.register(&system);
+
+    let improvements: Vec<_> = workers.iter().map(|_| AtomicUsize::new(0)).collect();
+    let mut times = vec![Duration::ZERO; workers.len()];
+    let mut migrations = vec![0; workers.len()];
+
+    let mut systems = vec![system; workers.len()];
+    let stop_reason = loop {
+        if let Some(reason) = criterion.check(&progress) {
+            break reason;
+        }
+
+        if cancel.is_cancelled() {
+            break StopReason::Interrupted;
+        }
+
+        let step = progress.steps;
+        let results: Vec<_> = systems
+            .into_par_iter()
+            .zip(workers.par_iter())
+            .zip(improvements.par_iter())
+            .map(|((mut system, worker), improvements)| {
+                let registerer = WorkerRegisterer { shared: &state_register, improvements, best: Cell::new(f64::MAX) };
+                let start = Instant::now();
+                (worker.algorithm)(&mut system, &registerer, step);
+                let best = registerer.best.get().min(system.energy());
+                (system, start.elapsed(), best)
+            })
+            .collect();
+
+        systems = Vec::with_capacity(workers.len());
+        let mut bests = Vec::with_capacity(workers.len());
+        for (i, (system, time, best)) in results.into_iter().enumerate() {
+            times[i] += time;
+            systems.push(system);
+            bests.push(best);
+        }
+        progress.record_replicas(&bests);
+
+        if migration_interval > 0 && (step + 1).is_multiple_of(migration_interval) {
+            if let Some(best) = state_register.minimal_state() {
+                let mut energies: Vec<_> = systems.iter().map(|s| OrderedFloat(s.energy())).collect();
+                energies.sort();
+                // Lower median, so that one of two workers still migrates
+                let median = energies[(energies.len() - 1) / 2];
+
+                for (i, system) in systems.iter_mut().enumerate() {
+                    if OrderedFloat(system.energy()) > median {
+                        system.set_system_state(best.state.clone());
+                        migrations[i] += 1;
+                    }
+                }
+            }
+        }
+
+        let flips = systems.iter().map(|s| s.flip_count()).sum::<u64>() - start_flips;
+        let mut inner = state_register.0.lock().unwrap();
+        let changed = inner.check_if_changed();
+        progress.update(&inner, changed, flips);
+        drop(inner);
+
+        observer.on_step(&progress);
+    };
+
+    let contributions = workers
+        .iter()
+        .enumerate()
+        .map(|(i, worker)| WorkerContribution {
+            name: worker.name.clone(),
+            improvements: improvements[i].load(Ordering::Relaxed),
+            best_energy: progress.replica_best.get(i).copied().unwrap_or(f64::MAX),
+            time: times[i],
+            migrations_received: migrations[i],
+        })
+        .collect();
+
+    PortfolioReport {
+        report: RunReport::new(state_register.minimal_state().expect("starting state is registered"), stop_reason, progress),
+        contributions,
+    }
+}
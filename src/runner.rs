@@ -1,10 +1,12 @@
-use std::cell::RefCell;
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::cell::{Cell, RefCell};
+use std::sync::Mutex;
+use std::time::Duration;
 use bitvec::vec::BitVec;
 use num_traits::Zero;
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
 use crate::System;
+use crate::cancel::CancellationToken;
+use crate::criteria::{RunProgress, StopCriterion, StopReason};
 
 #[derive(Debug, Clone)]
 pub struct State {
@@ -25,6 +27,7 @@ pub struct StateRegistererInner {
     current_minimal: Option<State>,
     previous_minimal: Option<State>,
     is_changed: bool,
+    registered: usize,
 }
 
 impl StateRegistererInner {
@@ -33,10 +36,12 @@ impl StateRegistererInner {
             current_minimal: None,
             previous_minimal: None,
             is_changed: false,
+            registered: 0,
         }
     }
 
     pub fn register(&mut self, system: &System) {
+        self.registered += 1;
         if self.current_minimal.as_ref().map_or(f64::MAX, |x| x.energy) > system.energy() {
             self.previous_minimal = self.current_minimal.replace(State {
                 energy: system.energy(),
@@ -50,6 +55,18 @@ impl StateRegistererInner {
         self.current_minimal.clone()
     }
 
+    pub fn minimal_energy(&self) -> Option<f64> {
+        self.current_minimal.as_ref().map(|x| x.energy)
+    }
+
+    pub fn previous_minimal_energy(&self) -> Option<f64> {
+        self.previous_minimal.as_ref().map(|x| x.energy)
+    }
+
+    pub fn registered_count(&self) -> usize {
+        self.registered
+    }
+
     pub fn check_if_changed(&mut self) -> bool {
         std::mem::replace(&mut self.is_changed, false)
     }
@@ -69,6 +86,31 @@ pub trait StateRegisterer {
     fn minimal_state(&self) -> Option<State>;
 }
 
+impl<T: StateRegisterer + ?Sized> StateRegisterer for &T {
+    #[inline(always)]
+    fn register(&self, system: &System) {
+        (**self).register(system);
+    }
+
+    #[inline(always)]
+    fn minimal_state(&self) -> Option<State> {
+        (**self).minimal_state()
+    }
+}
+
+impl<A: StateRegisterer, B: StateRegisterer> StateRegisterer for (A, B) {
+    #[inline(always)]
+    fn register(&self, system: &System) {
+        self.0.register(system);
+        self.1.register(system);
+    }
+
+    #[inline(always)]
+    fn minimal_state(&self) -> Option<State> {
+        self.0.minimal_state()
+    }
+}
+
 pub struct RefCellStateRegisterer(pub RefCell<StateRegistererInner>);
 
 impl StateRegisterer for RefCellStateRegisterer {
@@ -93,6 +135,35 @@ impl StateRegisterer for MutexStateRegisterer {
     }
 }
 
+// Registers into the registerer shared by all replicas of a run and keeps the lowest energy this
+// replica registered.
+pub struct ReplicaRegisterer<'a> {
+    shared: &'a MutexStateRegisterer,
+    best: Cell<f64>,
+}
+
+impl<'a> ReplicaRegisterer<'a> {
+    pub fn new(shared: &'a MutexStateRegisterer) -> Self {
+        Self { shared, best: Cell::new(f64::MAX) }
+    }
+
+    #[inline(always)]
+    pub fn best(&self) -> f64 {
+        self.best.get()
+    }
+}
+
+impl StateRegisterer for ReplicaRegisterer<'_> {
+    fn register(&self, system: &System) {
+        self.shared.register(system);
+        self.best.set(self.best.get().min(system.energy()));
+    }
+
+    fn minimal_state(&self) -> Option<State> {
+        self.shared.minimal_state()
+    }
+}
+
 pub trait AlgorithmState {
     fn after_step(&mut self);
 
@@ -120,87 +191,193 @@ impl AlgorithmState for Replicate {
     }
 }
 
-pub fn runner_one_thread<S: AlgorithmState, F: FnMut(&mut System, &RefCellStateRegisterer, &S)>(
+// Recalculates the energies of every system each `every` steps, dropping the drift accumulated
+// by the incremental updates.
+pub struct Resync<S> {
+    inner: S,
+    every: usize,
+    step: usize,
+}
+
+impl<S: AlgorithmState> Resync<S> {
+    pub fn new(inner: S, every: usize) -> Self {
+        Self { inner, every: every.max(1), step: 0 }
+    }
+}
+
+impl<S: AlgorithmState> AlgorithmState for Resync<S> {
+    fn after_step(&mut self) {
+        self.step += 1;
+        self.inner.after_step();
+    }
+
+    fn after_step_for_system<SR: StateRegisterer>(&self, system: &mut System, registerer: &SR) {
+        self.inner.after_step_for_system(system, registerer);
+        if self.step.is_multiple_of(self.every) {
+            system.recalculate_energy();
+        }
+    }
+}
+
+pub trait RunObserver {
+    fn on_step(&mut self, progress: &RunProgress);
+}
+
+impl RunObserver for () {
+    #[inline(always)]
+    fn on_step(&mut self, _progress: &RunProgress) {}
+}
+
+impl<F: FnMut(&RunProgress)> RunObserver for F {
+    fn on_step(&mut self, progress: &RunProgress) {
+        self(progress)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RunReport {
+    pub state: State,
+    pub stop_reason: StopReason,
+    pub steps: usize,
+    pub flips: u64,
+    pub registered_states: usize,
+    pub elapsed: Duration,
+    pub time_per_step: Duration,
+    pub energy_history: Vec<(Duration, f64)>,
+    pub replica_best: Vec<f64>,
+}
+
+impl RunReport {
+    pub fn new(state: State, stop_reason: StopReason, progress: RunProgress) -> Self {
+        Self {
+            state,
+            stop_reason,
+            steps: progress.steps,
+            flips: progress.flips,
+            registered_states: progress.registered_states,
+            elapsed: progress.started.elapsed(),
+            time_per_step: progress.time_per_step(),
+            energy_history: progress.minimal_history,
+            replica_best: progress.replica_best,
+        }
+    }
+}
+
+pub fn runner_one_thread<S: AlgorithmState, C: StopCriterion, F: FnMut(&mut System, &RefCellStateRegisterer, &S)>(
+    system: System,
+    criterion: C,
+    cancel: &CancellationToken,
+    algorithm_state: S,
+    f: F,
+) -> RunReport {
+    runner_one_thread_observed(system, criterion, cancel, algorithm_state, (), f)
+}
+
+pub fn runner_one_thread_observed<S: AlgorithmState, C: StopCriterion, O: RunObserver, F: FnMut(&mut System, &RefCellStateRegisterer, &S)>(
     mut system: System,
-    max_steps: usize,
+    mut criterion: C,
+    cancel: &CancellationToken,
     mut algorithm_state: S,
+    mut observer: O,
     mut f: F,
-) -> State {
-    let mut state_register = RefCellStateRegisterer(RefCell::new(StateRegistererInner::new()));
-    let mut steps = 0;
+) -> RunReport {
+    let state_register = RefCellStateRegisterer(RefCell::new(StateRegistererInner::new()));
+    let mut progress = RunProgress::new();
+    let start_flips = system.flip_count();
+
+    // The starting state is the result of a run stopped before anything else is registered
+    state_register.register(&system);
 
-    while !state_register.0.borrow().diff_between_mins(1e-8) {
-        if steps >= max_steps {
-            break;
+    let stop_reason = loop {
+        if let Some(reason) = criterion.check(&progress) {
+            break reason;
+        }
+
+        if cancel.is_cancelled() {
+            break StopReason::Interrupted;
         }
 
         f(&mut system, &state_register, &algorithm_state);
+        let best = state_register.0.borrow().minimal_energy().unwrap_or(f64::MAX);
+        progress.record_replicas(&[best.min(system.energy())]);
 
         algorithm_state.after_step();
         algorithm_state.after_step_for_system(&mut system, &state_register);
 
-        if state_register.0.borrow_mut().check_if_changed() {
-            steps = 0;
-        } else {
-            steps += 1;
-        }
-    }
+        let mut inner = state_register.0.borrow_mut();
+        let changed = inner.check_if_changed();
+        progress.update(&inner, changed, system.flip_count() - start_flips);
+        drop(inner);
 
-    state_register.minimal_state().unwrap()
+        observer.on_step(&progress);
+    };
+
+    RunReport::new(state_register.minimal_state().expect("starting state is registered"), stop_reason, progress)
 }
 
-lazy_static::lazy_static! {
-    static ref WORKING: Arc<AtomicBool> = {
-        let working = Arc::new(AtomicBool::new(true));
-        let w = working.clone();
-        ctrlc::set_handler(move || {
-            println!("Wait step for closing");
-            w.store(false, Ordering::SeqCst);
-        }).unwrap();
-        working
-    };
+pub fn runner_multi_thread<S: AlgorithmState + Sync, C: StopCriterion, F: Fn(&mut System, &ReplicaRegisterer, &S) + Sync + Send>(
+    system: System,
+    algorithm_state: S,
+    criterion: C,
+    cancel: &CancellationToken,
+    thread_count: usize,
+    f: F,
+) -> RunReport {
+    runner_multi_thread_observed(system, algorithm_state, criterion, cancel, thread_count, (), f)
 }
 
-pub fn runner_multi_thread<S: AlgorithmState + Sync, F: Fn(&mut System, &MutexStateRegisterer, &S) + Sync + Send>(
-    mut system: System,
+pub fn runner_multi_thread_observed<S: AlgorithmState + Sync, C: StopCriterion, O: RunObserver, F: Fn(&mut System, &ReplicaRegisterer, &S) + Sync + Send>(
+    system: System,
     mut algorithm_state: S,
-    max_steps: usize,
+    mut criterion: C,
+    cancel: &CancellationToken,
     thread_count: usize,
-    mut f: F,
-) -> State {
-    let mut state_register = MutexStateRegisterer(Mutex::new(StateRegistererInner::new()));
-    let mut steps = 0;
-    let mut all_steps = 0;
+    mut observer: O,
+    f: F,
+) -> RunReport {
+    let state_register = MutexStateRegisterer(Mutex::new(StateRegistererInner::new()));
+    let mut progress = RunProgress::new();
+    let start_flips = system.flip_count() * thread_count as u64;
+
+    // The starting state is the result of a run stopped before anything else is registered
+    state_register.register(&system);
 
     let mut systems = vec![system.clone(); thread_count];
-    while !state_register.0.lock().unwrap().diff_between_mins(1e-8) && WORKING.load(Ordering::SeqCst) {
+    let stop_reason = loop {
+        if let Some(reason) = criterion.check(&progress) {
+            break reason;
+        }
 
-        if steps >= max_steps {
-            break;
+        if cancel.is_cancelled() {
+            break StopReason::Interrupted;
         }
 
-        systems = systems
+        let bests: Vec<f64>;
+        (systems, bests) = systems
             .into_par_iter()
             .map(|mut system| {
-                f(&mut system, &state_register, &algorithm_state);
-                system
+                let registerer = ReplicaRegisterer::new(&state_register);
+                f(&mut system, &registerer, &algorithm_state);
+                let best = registerer.best().min(system.energy());
+                (system, best)
             })
-            .collect();
+            .unzip();
+        progress.record_replicas(&bests);
 
         algorithm_state.after_step();
         for system in &mut systems {
             algorithm_state.after_step_for_system(system, &state_register);
         }
 
-        if state_register.0.lock().unwrap().check_if_changed() {
-            steps = 0;
-        } else {
-            steps += 1;
-        }
+        let flips = systems.iter().map(|s| s.flip_count()).sum::<u64>() - start_flips;
+        let mut inner = state_register.0.lock().unwrap();
+        let changed = inner.check_if_changed();
+        progress.update(&inner, changed, flips);
+        drop(inner);
 
-        all_steps += 1;
-    }
+        observer.on_step(&progress);
+    };
 
-    state_register.minimal_state().unwrap()
+    RunReport::new(state_register.minimal_state().expect("starting state is registered"), stop_reason, progress)
 }
 
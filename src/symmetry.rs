@@ -0,0 +1,148 @@
+use std::f64::consts::PI;
+use bitvec::prelude::BitVec;
+use ordered_float::OrderedFloat;
+use vek::Mat2;
+use crate::system::Vec2;
+use crate::System;
+
+const EPS: f64 = 1e-6;
+
+// Elements sorted by position, to find the ones at a given position within EPS.
+#[derive(Debug, Clone)]
+pub struct PositionLookup {
+    sorted: Vec<(Vec2, usize)>,
+}
+
+impl PositionLookup {
+    pub fn new(system: &System) -> Self {
+        let mut sorted: Vec<_> = system.elements()
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (e.pos.map(|x| x.0), i))
+            .collect();
+        sorted.sort_by_key(|(p, _)| (OrderedFloat(p.x), OrderedFloat(p.y)));
+        Self { sorted }
+    }
+
+    pub fn at(&self, pos: Vec2) -> impl Iterator<Item = usize> + '_ {
+        let start = self.sorted.partition_point(|(p, _)| p.x < pos.x - EPS);
+        self.sorted[start..]
+            .iter()
+            .take_while(move |(p, _)| p.x <= pos.x + EPS)
+            .filter(move |(p, _)| (p.y - pos.y).abs() <= EPS)
+            .map(|(_, i)| *i)
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SpinSymmetry {
+    pub permutation: Vec<usize>,
+    pub inversion: BitVec,
+}
+
+impl SpinSymmetry {
+    pub fn identity(size: usize) -> Self {
+        Self {
+            permutation: (0..size).collect(),
+            inversion: BitVec::repeat(false, size),
+        }
+    }
+
+    pub fn global_flip(size: usize) -> Self {
+        Self {
+            permutation: (0..size).collect(),
+            inversion: BitVec::repeat(true, size),
+        }
+    }
+
+    // Element i goes to permutation[i]; inversion[i] is set when the transformed
+    // moment of i is opposite to the default moment of permutation[i].
+    pub fn from_transform(system: &System, transform: impl Fn(Vec2) -> Vec2, rotation: Mat2<f64>) -> Option<Self> {
+        let elements = system.elements();
+        let lookup = PositionLookup::new(system);
+
+        let mut permutation = Vec::with_capacity(elements.len());
+        let mut inversion = BitVec::with_capacity(elements.len());
+
+        for e in elements {
+            let pos = transform(e.pos.map(|x| x.0));
+            let j = lookup.at(pos).next()?;
+
+            let magn = rotation * e.magn();
+            let target = elements[j].magn();
+            if magn.distance(target) <= EPS * target.magnitude().max(1.0) {
+                inversion.push(false);
+            } else if magn.distance(-target) <= EPS * target.magnitude().max(1.0) {
+                inversion.push(true);
+            } else {
+                return None;
+            }
+            permutation.push(j);
+        }
+
+        Some(Self { permutation, inversion })
+    }
+
+    // Rotations by multiples of 60 and 90 degrees and the matching mirrors around
+    // the center of the bounding box, keeping only the ones mapping the lattice onto itself.
+    pub fn point_group(system: &System) -> Vec<Self> {
+        let elements = system.elements();
+        let (min, max) = elements.iter().fold(
+            (Vec2::broadcast(f64::MAX), Vec2::broadcast(f64::MIN)),
+            |(min, max), e| {
+                let p = e.pos.map(|x| x.0);
+                (Vec2::partial_min(min, p), Vec2::partial_max(max, p))
+            },
+        );
+        let center = (min + max) / 2.0;
+
+        let mut angles: Vec<f64> = (1..6).map(|k| k as f64 * PI / 3.0).collect();
+        angles.extend([PI / 2.0, 3.0 * PI / 2.0]);
+
+        let mut matrices: Vec<Mat2<f64>> = angles.iter().map(|a| Mat2::rotation_z(*a)).collect();
+        let mirror = Mat2::new(-1.0, 0.0, 0.0, 1.0);
+        matrices.push(mirror);
+        matrices.extend(angles.iter().map(|a| Mat2::rotation_z(*a) * mirror));
+
+        let mut symmetries = vec![Self::identity(system.size())];
+        for m in matrices {
+            if let Some(s) = Self::from_transform(system, |p| m * (p - center) + center, m) {
+                if !symmetries.contains(&s) {
+                    symmetries.push(s);
+                }
+            }
+        }
+
+        symmetries
+    }
+
+    pub fn apply(&self, state: &BitVec) -> BitVec {
+        let mut result = BitVec::repeat(false, state.len());
+        for (i, s) in state.iter().enumerate() {
+            result.set(self.permutation[i], *s ^ self.inversion[i]);
+        }
+        result
+    }
+}
+
+pub fn canonical_state(state: &BitVec, symmetries: &[SpinSymmetry], global_flip: bool) -> BitVec {
+    let mut best = state.clone();
+    let mut check = |candidate: BitVec| {
+        if global_flip {
+            let flipped = !candidate.clone();
+            if flipped < best {
+                best = flipped;
+            }
+        }
+        if candidate < best {
+            best = candidate;
+        }
+    };
+
+    check(state.clone());
+    for s in symmetries {
+        check(s.apply(state));
+    }
+
+    best
+}
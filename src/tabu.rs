@@ -0,0 +1,28 @@
+use ordered_float::OrderedFloat;
+use crate::{StateRegisterer, System};
+
+pub fn tabu_search(system: &mut System, registerer: &impl StateRegisterer, tenure: usize, steps: usize) {
+    let size = system.size();
+    let mut tabu_until = vec![0usize; size];
+    let mut best = system.energy();
+
+    registerer.register(system);
+
+    for step in 1..=steps {
+        let candidate = (0..size)
+            .map(|i| (i, system.flip_delta(i)))
+            .filter(|(i, d)| tabu_until[*i] < step || system.energy() + d < best)
+            .min_by_key(|(_, d)| OrderedFloat(*d));
+
+        let index = match candidate {
+            Some((index, _)) => index,
+            None => continue,
+        };
+
+        system.reverse_spin(index);
+        registerer.register(system);
+
+        tabu_until[index] = step + tenure;
+        best = best.min(system.energy());
+    }
+}
@@ -0,0 +1,277 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use crate::metropolis::metropolis_mc_step_observed;
+use crate::observables::{Magnetization, Sublattices};
+use crate::{StateRegisterer, System};
+
+const JACKKNIFE_BLOCKS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Estimate {
+    pub mean: f64,
+    pub error: f64,
+    // Integrated autocorrelation time in samples, 0.5 for independent ones
+    pub tau: f64,
+    pub samples: usize,
+}
+
+impl Display for Estimate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.6e} +- {:.2e} (tau {:.1})", self.mean, self.error, self.tau)
+    }
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len().max(1) as f64
+}
+
+// Standard error of the mean by blocking (Flyvbjerg-Petersen): the naive error of the block means
+// grows with the block size until blocks are longer than the correlations. The largest value over
+// the levels with at least 16 blocks is taken as the plateau.
+pub fn blocking_error(values: &[f64]) -> f64 {
+    let mut blocks = values.to_vec();
+    let mut error = 0.0f64;
+
+    while blocks.len() >= 16 {
+        let n = blocks.len() as f64;
+        let m = mean(&blocks);
+        let variance = blocks.iter().map(|x| (x - m).powi(2)).sum::<f64>() / (n - 1.0);
+        error = error.max((variance / n).sqrt());
+
+        blocks = blocks.chunks_exact(2).map(|c| (c[0] + c[1]) / 2.0).collect();
+    }
+
+    error
+}
+
+// Integrated autocorrelation time 1/2 + sum of the normalized autocorrelations, summed up to the
+// first window W >= 5 tau(W) (Sokal).
+pub fn autocorrelation_time(values: &[f64]) -> f64 {
+    let n = values.len();
+    let m = mean(values);
+    let variance = values.iter().map(|x| (x - m).powi(2)).sum::<f64>() / n.max(1) as f64;
+    if n < 2 || variance <= 0.0 {
+        return 0.5;
+    }
+
+    let mut tau = 0.5;
+    for lag in 1..n {
+        let covariance = values.iter().zip(&values[lag..]).map(|(a, b)| (a - m) * (b - m)).sum::<f64>() / (n - lag) as f64;
+        tau += covariance / variance;
+        if lag as f64 >= 5.0 * tau {
+            break;
+        }
+    }
+
+    tau.max(0.5)
+}
+
+pub fn estimate(values: &[f64]) -> Estimate {
+    Estimate {
+        mean: mean(values),
+        error: blocking_error(values),
+        tau: autocorrelation_time(values),
+        samples: values.len(),
+    }
+}
+
+// factor * (<x^2> - <x>^2) with a jackknife error over consecutive blocks, for C_v and chi.
+pub fn fluctuation(values: &[f64], factor: f64) -> Estimate {
+    let variance = |sum: f64, squares: f64, n: f64| factor * (squares / n - (sum / n).powi(2));
+    let n = values.len();
+    let sum = values.iter().sum::<f64>();
+    let squares = values.iter().map(|x| x * x).sum::<f64>();
+    let value = variance(sum, squares, n.max(1) as f64);
+
+    let block = n / JACKKNIFE_BLOCKS;
+    let error = if block == 0 {
+        0.0
+    } else {
+        let blocks: Vec<(f64, f64)> = values[..block * JACKKNIFE_BLOCKS]
+            .chunks_exact(block)
+            .map(|c| (c.iter().sum::<f64>(), c.iter().map(|x| x * x).sum::<f64>()))
+            .collect();
+        let (total, total_squares) = blocks.iter().fold((0.0, 0.0), |(s, q), b| (s + b.0, q + b.1));
+        let rest = ((JACKKNIFE_BLOCKS - 1) * block) as f64;
+
+        let leave_out: Vec<f64> = blocks.iter().map(|(s, q)| variance(total - s, total_squares - q, rest)).collect();
+        let jackknife_mean = mean(&leave_out);
+        let b = JACKKNIFE_BLOCKS as f64;
+        ((b - 1.0) / b * leave_out.iter().map(|x| (x - jackknife_mean).powi(2)).sum::<f64>()).sqrt()
+    };
+
+    Estimate {
+        mean: value,
+        error,
+        tau: autocorrelation_time(values),
+        samples: n,
+    }
+}
+
+pub struct NamedObservable {
+    pub name: String,
+    pub function: Box<dyn Fn(&System) -> f64 + Send + Sync>,
+}
+
+impl NamedObservable {
+    pub fn new(name: impl Into<String>, function: impl Fn(&System) -> f64 + Send + Sync + 'static) -> Self {
+        Self { name: name.into(), function: Box::new(function) }
+    }
+}
+
+// Time series of one run at a fixed temperature; samples before `burn_in` are dropped.
+pub struct Measurements<'a> {
+    pub temperature: f64,
+    size: usize,
+    burn_in: usize,
+    seen: usize,
+    energy: Vec<f64>,
+    magnetization: Vec<f64>,
+    observables: &'a [NamedObservable],
+    values: Vec<Vec<f64>>,
+}
+
+impl<'a> Measurements<'a> {
+    pub fn new(system: &System, temperature: f64, burn_in: usize, observables: &'a [NamedObservable]) -> Self {
+        Self {
+            temperature,
+            size: system.size(),
+            burn_in,
+            seen: 0,
+            energy: Vec::new(),
+            magnetization: Vec::new(),
+            observables,
+            values: vec![Vec::new(); observables.len()],
+        }
+    }
+
+    // Energy and |magnetization| are stored per spin.
+    pub fn record(&mut self, system: &System, magnetization: f64) {
+        self.seen += 1;
+        if self.seen <= self.burn_in {
+            return;
+        }
+
+        let size = self.size as f64;
+        self.energy.push(system.energy() / size);
+        self.magnetization.push(magnetization / size);
+        for (values, observable) in self.values.iter_mut().zip(self.observables) {
+            values.push((observable.function)(system));
+        }
+    }
+
+    pub fn report(&self) -> ThermoReport {
+        let size = self.size as f64;
+        let t = self.temperature;
+
+        ThermoReport {
+            temperature: t,
+            energy: estimate(&self.energy),
+            specific_heat: fluctuation(&self.energy, size / (t * t)),
+            magnetization: estimate(&self.magnetization),
+            susceptibility: fluctuation(&self.magnetization, size / t),
+            observables: self.observables
+                .iter()
+                .zip(&self.values)
+                .map(|(o, v)| (o.name.clone(), estimate(v)))
+                .collect(),
+        }
+    }
+}
+
+// Per spin energy and magnetization with C_v = N (<e^2> - <e>^2) / T^2 and
+// chi = N (<m^2> - <m>^2) / T, where m is the magnitude of the magnetization per spin.
+#[derive(Debug, Clone)]
+pub struct ThermoReport {
+    pub temperature: f64,
+    pub energy: Estimate,
+    pub specific_heat: Estimate,
+    pub magnetization: Estimate,
+    pub susceptibility: Estimate,
+    pub observables: Vec<(String, Estimate)>,
+}
+
+impl Display for ThermoReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "T = {} ({} samples)", self.temperature, self.energy.samples)?;
+        writeln!(f, "  E   = {}", self.energy)?;
+        writeln!(f, "  C_v = {}", self.specific_heat)?;
+        writeln!(f, "  |M| = {}", self.magnetization)?;
+        write!(f, "  chi = {}", self.susceptibility)?;
+        for (name, estimate) in &self.observables {
+            write!(f, "\n  {} = {}", name, estimate)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn write_csv(reports: &[ThermoReport], path: impl AsRef<Path>) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "t,samples,e,e_error,e_tau,cv,cv_error,m,m_error,m_tau,chi,chi_error")?;
+    if let Some(report) = reports.first() {
+        for (name, _) in &report.observables {
+            write!(writer, ",{},{}_error,{}_tau", name, name, name)?;
+        }
+    }
+    writeln!(writer)?;
+
+    for r in reports {
+        write!(
+            writer,
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            r.temperature,
+            r.energy.samples,
+            r.energy.mean,
+            r.energy.error,
+            r.energy.tau,
+            r.specific_heat.mean,
+            r.specific_heat.error,
+            r.magnetization.mean,
+            r.magnetization.error,
+            r.magnetization.tau,
+            r.susceptibility.mean,
+            r.susceptibility.error,
+        )?;
+        for (_, e) in &r.observables {
+            write!(writer, ",{},{},{}", e.mean, e.error, e.tau)?;
+        }
+        writeln!(writer)?;
+    }
+
+    writer.flush()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplingConfig {
+    // Sweeps of `size` Metropolis steps measured at every temperature
+    pub sweeps: usize,
+    // Sweeps discarded before measuring at every temperature
+    pub burn_in: usize,
+}
+
+// Metropolis sampling at each temperature in turn, every one starting from the last state of the
+// previous one, measured once per sweep.
+pub fn metropolis_measure(
+    system: &mut System,
+    registerer: &impl StateRegisterer,
+    temperatures: &[f64],
+    config: SamplingConfig,
+    observables: &[NamedObservable],
+) -> Vec<ThermoReport> {
+    let size = system.size();
+    let mut magnetization = Magnetization::new(system, Sublattices::new(vec![0; size]), Vec::new());
+
+    temperatures
+        .iter()
+        .map(|temp| {
+            let mut measurements = Measurements::new(system, *temp, config.burn_in, observables);
+            for _ in 0..config.burn_in + config.sweeps {
+                metropolis_mc_step_observed(system, registerer, *temp, size, |s, spin| magnetization.update(s, spin));
+                measurements.record(system, magnetization.total().magnitude());
+            }
+            measurements.report()
+        })
+        .collect()
+}
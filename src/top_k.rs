@@ -0,0 +1,145 @@
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use bitvec::prelude::BitVec;
+use crate::runner::{State, StateRegisterer};
+use crate::symmetry::{canonical_state, SpinSymmetry};
+use crate::System;
+
+// At most `count` items with the lowest energies, sorted by energy.
+#[derive(Debug, Clone)]
+pub struct Lowest<T> {
+    count: usize,
+    items: Vec<(f64, T)>,
+}
+
+impl<T> Lowest<T> {
+    pub fn new(count: usize) -> Self {
+        Self { count, items: Vec::with_capacity(count + 1) }
+    }
+
+    // Energy an item must be below to be kept.
+    pub fn threshold(&self) -> f64 {
+        if self.items.len() < self.count {
+            f64::MAX
+        } else {
+            self.items.last().map_or(f64::MAX, |(e, _)| *e)
+        }
+    }
+
+    pub fn push(&mut self, energy: f64, item: T) {
+        if energy >= self.threshold() {
+            return;
+        }
+
+        let index = self.items.partition_point(|(e, _)| *e <= energy);
+        self.items.insert(index, (energy, item));
+        self.items.truncate(self.count);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.items.iter().map(|(_, item)| item)
+    }
+
+    pub fn into_vec(self) -> Vec<T> {
+        self.items.into_iter().map(|(_, item)| item).collect()
+    }
+}
+
+pub struct TopKStatesInner {
+    global_flip: bool,
+    symmetries: Vec<SpinSymmetry>,
+    states: Lowest<(BitVec, State)>,
+}
+
+impl TopKStatesInner {
+    pub fn new(count: usize) -> Self {
+        Self {
+            global_flip: false,
+            symmetries: Vec::new(),
+            states: Lowest::new(count),
+        }
+    }
+
+    pub fn with_global_flip(mut self) -> Self {
+        self.global_flip = true;
+        self
+    }
+
+    pub fn with_symmetries(mut self, symmetries: Vec<SpinSymmetry>) -> Self {
+        self.symmetries = symmetries;
+        self
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.states.threshold()
+    }
+
+    pub fn register(&mut self, system: &System) {
+        let energy = system.energy();
+        if energy >= self.threshold() {
+            return;
+        }
+
+        let key = canonical_state(system.system_state(), &self.symmetries, self.global_flip);
+        if self.states.iter().any(|(k, _)| *k == key) {
+            return;
+        }
+
+        self.states.push(energy, (key, State {
+            energy,
+            state: system.system_state().clone(),
+        }));
+    }
+
+    pub fn minimal_state(&self) -> Option<State> {
+        self.states.iter().next().map(|(_, s)| s.clone())
+    }
+
+    pub fn states(&self) -> Vec<State> {
+        self.states.iter().map(|(_, s)| s.clone()).collect()
+    }
+}
+
+pub struct TopKStateRegisterer {
+    inner: Mutex<TopKStatesInner>,
+    threshold: AtomicU64,
+}
+
+impl TopKStateRegisterer {
+    pub fn new(inner: TopKStatesInner) -> Self {
+        Self {
+            threshold: AtomicU64::new(inner.threshold().to_bits()),
+            inner: Mutex::new(inner),
+        }
+    }
+
+    pub fn states(&self) -> Vec<State> {
+        self.inner.lock().unwrap().states()
+    }
+
+    // Writes states sorted by energy as `{prefix}_{index}.mfsys`, index starting from 0.
+    pub fn save_mfsys(&self, system: &System, prefix: impl AsRef<Path>) {
+        let mut system = system.clone();
+        for (i, state) in self.states().into_iter().enumerate() {
+            system.set_system_state(state.state);
+            system.save_mfsys(format!("{}_{}.mfsys", prefix.as_ref().display(), i));
+        }
+    }
+}
+
+impl StateRegisterer for TopKStateRegisterer {
+    fn register(&self, system: &System) {
+        if system.energy() >= f64::from_bits(self.threshold.load(Ordering::Relaxed)) {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.register(system);
+        self.threshold.store(inner.threshold().to_bits(), Ordering::Relaxed);
+    }
+
+    fn minimal_state(&self) -> Option<State> {
+        self.inner.lock().unwrap().minimal_state()
+    }
+}
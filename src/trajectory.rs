@@ -0,0 +1,215 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use bitvec::prelude::BitVec;
+use crate::runner::{State, StateRegisterer, StateRegistererInner};
+use crate::System;
+use crate::utils::{read_state, write_state};
+
+const MAGIC: &[u8; 4] = b"MFTR";
+const VERSION: u32 = 1;
+
+// File layout (little endian):
+//   header: "MFTR", version u32, size u64, initial state packed in ceil(size / 8) bytes
+//   frame:  step u64, energy f64, flip count u32, flipped indexes u32 * count
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub step: u64,
+    pub energy: f64,
+    pub flips: Vec<u32>,
+}
+
+struct TrajectoryWriterInner {
+    writer: BufWriter<File>,
+    previous: BitVec,
+    step: u64,
+    flips: Vec<u32>,
+    // The first write error, nothing is written after it
+    error: Option<std::io::Error>,
+}
+
+pub struct TrajectoryStateRegisterer {
+    inner: Mutex<TrajectoryWriterInner>,
+    minimal: Mutex<StateRegistererInner>,
+}
+
+impl TrajectoryStateRegisterer {
+    pub fn create(system: &System, filename: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(filename)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(system.size() as u64).to_le_bytes())?;
+        write_state(&mut writer, system.system_state())?;
+
+        Ok(Self {
+            inner: Mutex::new(TrajectoryWriterInner {
+                writer,
+                previous: system.system_state().clone(),
+                step: 0,
+                flips: Vec::new(),
+                error: None,
+            }),
+            minimal: Mutex::new(StateRegistererInner::new()),
+        })
+    }
+
+    // Returns the first error of the writes made by register, if there was one.
+    pub fn flush(&self) -> std::io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        match &inner.error {
+            Some(e) => Err(std::io::Error::new(e.kind(), e.to_string())),
+            None => inner.writer.flush(),
+        }
+    }
+
+    pub fn finish(self) -> std::io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.error.take() {
+            Some(e) => Err(e),
+            None => inner.writer.flush(),
+        }
+    }
+}
+
+// Indexes of the bits differing between two states of the same length, compared word by word.
+fn diff_ones(previous: &BitVec, state: &BitVec, flips: &mut Vec<u32>) {
+    flips.clear();
+    let words = previous.as_raw_slice().iter().zip(state.as_raw_slice());
+    for (w, (a, b)) in words.enumerate() {
+        let mut diff = a ^ b;
+        while diff != 0 {
+            let index = w * usize::BITS as usize + diff.trailing_zeros() as usize;
+            if index < state.len() {
+                flips.push(index as u32);
+            }
+            diff &= diff - 1;
+        }
+    }
+}
+
+impl StateRegisterer for TrajectoryStateRegisterer {
+    fn register(&self, system: &System) {
+        let mut inner = self.inner.lock().unwrap();
+        let TrajectoryWriterInner { writer, previous, step, flips, error } = &mut *inner;
+
+        if error.is_none() {
+            diff_ones(previous, system.system_state(), flips);
+
+            let result = writer.write_all(&step.to_le_bytes())
+                .and_then(|_| writer.write_all(&system.energy().to_le_bytes()))
+                .and_then(|_| writer.write_all(&(flips.len() as u32).to_le_bytes()))
+                .and_then(|_| flips.iter().try_for_each(|f| writer.write_all(&f.to_le_bytes())));
+
+            match result {
+                Ok(()) => {
+                    *step += 1;
+                    for f in flips.iter() {
+                        let f = *f as usize;
+                        let bit = previous[f];
+                        previous.set(f, !bit);
+                    }
+                }
+                Err(e) => *error = Some(e),
+            }
+        }
+        drop(inner);
+
+        self.minimal.lock().unwrap().register(system);
+    }
+
+    fn minimal_state(&self) -> Option<State> {
+        self.minimal.lock().unwrap().minimal_state()
+    }
+}
+
+impl Drop for TrajectoryStateRegisterer {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+pub struct TrajectoryReader {
+    reader: BufReader<File>,
+    size: usize,
+    initial: BitVec,
+}
+
+impl TrajectoryReader {
+    pub fn open(filename: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut reader = BufReader::new(File::open(filename)?);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        if &magic != MAGIC || u32::from_le_bytes(version) != VERSION {
+            return Err(std::io::Error::new(ErrorKind::InvalidData, "Not a trajectory file"));
+        }
+
+        let mut size = [0u8; 8];
+        reader.read_exact(&mut size)?;
+        let size = u64::from_le_bytes(size) as usize;
+        let initial = read_state(&mut reader, size)?;
+
+        Ok(Self { reader, size, initial })
+    }
+
+    #[inline(always)]
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    #[inline(always)]
+    pub fn initial_state(&self) -> &BitVec {
+        &self.initial
+    }
+
+    pub fn next_frame(&mut self) -> std::io::Result<Option<Frame>> {
+        let mut step = [0u8; 8];
+        match self.reader.read_exact(&mut step) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            r => r?,
+        }
+
+        let mut energy = [0u8; 8];
+        self.reader.read_exact(&mut energy)?;
+        let mut count = [0u8; 4];
+        self.reader.read_exact(&mut count)?;
+
+        let count = u32::from_le_bytes(count) as usize;
+        let mut bytes = vec![0u8; count * 4];
+        self.reader.read_exact(&mut bytes)?;
+
+        Ok(Some(Frame {
+            step: u64::from_le_bytes(step),
+            energy: f64::from_le_bytes(energy),
+            flips: bytes
+                .chunks_exact(4)
+                .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                .collect(),
+        }))
+    }
+
+    pub fn reset_system(&self, system: &mut System) {
+        assert_eq!(self.size, system.size());
+        system.set_system_state(self.initial.clone());
+    }
+
+    // Applies the next frame to a system that already follows this trajectory.
+    pub fn replay_step(&mut self, system: &mut System) -> std::io::Result<Option<Frame>> {
+        let frame = self.next_frame()?;
+        if let Some(frame) = &frame {
+            system.reverse_spins(frame.flips.iter().map(|i| *i as usize));
+        }
+        Ok(frame)
+    }
+
+    pub fn replay(mut self, system: &mut System, mut f: impl FnMut(&System, &Frame)) -> std::io::Result<()> {
+        self.reset_system(system);
+        while let Some(frame) = self.replay_step(system)? {
+            f(system, &frame);
+        }
+        Ok(())
+    }
+}
@@ -1,15 +1,34 @@
 use std::f64::consts::PI;
+use std::io::{Read, Write};
 use bitvec::prelude::BitVec;
-use plotters::prelude::{Polygon, ShapeStyle};
+use std::path::Path;
+use ordered_float::OrderedFloat;
+use plotters::prelude::{BitMapBackend, Circle, Color, EmptyElement, IntoDrawingArea, Polygon, Rectangle, RGBColor, ShapeStyle};
 use vek::{Mat2, Vec2};
 use crate::{Element, System};
 
+pub type Rgb = (u8, u8, u8);
+
 pub fn grey_bitvec(g: BitVec) -> BitVec {
     let mut g1 = g.clone();
     g1.shift_right(1);
     g ^ g1
 }
 
+pub fn write_state(writer: &mut impl Write, state: &BitVec) -> std::io::Result<()> {
+    let mut bytes = vec![0u8; state.len().div_ceil(8)];
+    for i in state.iter_ones() {
+        bytes[i / 8] |= 1 << (i % 8);
+    }
+    writer.write_all(&bytes)
+}
+
+pub fn read_state(reader: &mut impl Read, size: usize) -> std::io::Result<BitVec> {
+    let mut bytes = vec![0u8; size.div_ceil(8)];
+    reader.read_exact(&mut bytes)?;
+    Ok((0..size).map(|i| bytes[i / 8] & (1 << (i % 8)) != 0).collect())
+}
+
 pub fn get_part_from_system(system: &System, elements: &[usize]) -> Vec<Element> {
     let mut elements: Vec<_> = system
         .elements()
@@ -48,4 +67,74 @@ pub fn generate_arrow<S: Into<ShapeStyle>>(l: f64, h: f64, ah: f64, al: f64, dir
         .map(|v| (v.x as i32, v.y as i32));
 
     Polygon::new(points, style)
-}
\ No newline at end of file
+}
+
+// Row-major grid of values as a PNG, row 0 at the bottom and every cell `scale` pixels wide.
+// Values go from white at the minimum to dark blue at the maximum, NaN cells are gray.
+pub fn write_heatmap(path: impl AsRef<Path>, values: &[f64], width: usize, height: usize, scale: u32) -> anyhow::Result<()> {
+    let finite = values.iter().copied().filter(|v| v.is_finite());
+    let min = finite.clone().min_by_key(|v| OrderedFloat(*v)).unwrap_or(0.0);
+    let max = finite.max_by_key(|v| OrderedFloat(*v)).unwrap_or(0.0);
+    let range = if max > min { max - min } else { 1.0 };
+
+    let root = BitMapBackend::new(path.as_ref(), (width as u32 * scale, height as u32 * scale)).into_drawing_area();
+    for row in 0..height {
+        for col in 0..width {
+            let value = values[row * width + col];
+            let color = if value.is_finite() {
+                let t = (value - min) / range;
+                RGBColor((255.0 * (1.0 - t)) as u8, (255.0 * (1.0 - 0.8 * t)) as u8, (255.0 - 100.0 * t) as u8)
+            } else {
+                RGBColor(127, 127, 127)
+            };
+
+            let x = col as i32 * scale as i32;
+            let y = (height - 1 - row) as i32 * scale as i32;
+            root.draw(&Rectangle::new([(x, y), (x + scale as i32, y + scale as i32)], color.filled()))?;
+        }
+    }
+
+    root.present()?;
+    Ok(())
+}
+
+// Every element as an arrow in the direction of its actual moment filled with colors[i] on gray,
+// and markers as filled circles, scaled to `width` pixels. Arrows and markers are sized by the
+// nearest neighbour distance.
+pub fn write_element_map(
+    path: impl AsRef<Path>,
+    system: &System,
+    colors: &[Rgb],
+    markers: &[(Vec2<f64>, Rgb)],
+    width: u32,
+) -> anyhow::Result<()> {
+    let positions: Vec<_> = system.elements().iter().map(|e| e.pos.map(|x| x.0)).collect();
+    let min = positions.iter().copied().reduce(Vec2::partial_min).unwrap_or_default();
+    let max = positions.iter().copied().reduce(Vec2::partial_max).unwrap_or_default();
+    let nearest = system.nearest_distance();
+    let nearest = if nearest.is_finite() { nearest } else { 1.0 };
+
+    let margin = nearest;
+    let scale = (width as f64 - 1.0) / (max.x - min.x + 2.0 * margin).max(f64::MIN_POSITIVE);
+    let height = ((max.y - min.y + 2.0 * margin) * scale).ceil() as u32 + 1;
+    let pixel = |p: Vec2<f64>| (((p.x - min.x + margin) * scale) as i32, ((p.y - min.y + margin) * scale) as i32);
+
+    let root = BitMapBackend::new(path.as_ref(), (width, height)).into_drawing_area();
+    root.fill(&RGBColor(127, 127, 127))?;
+
+    // generate_arrow is 80 units long before scaling
+    let arrow_scale = 0.8 * nearest * scale / 80.0;
+    for (i, element) in system.elements().iter().enumerate() {
+        let (r, g, b) = colors[i];
+        let direction = element.magn() * system.system_signs()[i];
+        root.draw(&(EmptyElement::at(pixel(positions[i])) + generate_arrow(40., 7., 14., 20., direction, arrow_scale, RGBColor(r, g, b).filled())))?;
+    }
+
+    let radius = ((0.2 * nearest * scale) as i32).max(2);
+    for (position, (r, g, b)) in markers {
+        root.draw(&Circle::new(pixel(*position), radius, RGBColor(*r, *g, *b).filled()))?;
+    }
+
+    root.present()?;
+    Ok(())
+}
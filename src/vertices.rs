@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use crate::system::Vec2;
+use crate::utils::Rgb;
+use crate::System;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VertexConfig {
+    // Distance from the element center to its ends along the moment, half of the nearest
+    // neighbour distance by default
+    pub reach: Option<f64>,
+    // Ends closer than this belong to one vertex, `reach` by default
+    pub tolerance: Option<f64>,
+    // Groups with fewer ends are free ends, not vertices
+    pub min_coordination: usize,
+}
+
+impl Default for VertexConfig {
+    fn default() -> Self {
+        Self {
+            reach: None,
+            tolerance: None,
+            min_coordination: 2,
+        }
+    }
+}
+
+// End of an element at a vertex; `head` is the end the default moment points to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VertexEnd {
+    pub element: usize,
+    pub head: bool,
+}
+
+impl VertexEnd {
+    // Whether the actual moment points into the vertex; a set state bit reverses the moment
+    #[inline(always)]
+    pub fn is_in(&self, system: &System) -> bool {
+        self.head != system.system_state()[self.element]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Vertex {
+    pub position: Vec2,
+    pub ends: Vec<VertexEnd>,
+}
+
+impl Vertex {
+    #[inline(always)]
+    pub fn coordination(&self) -> usize {
+        self.ends.len()
+    }
+
+    pub fn classify(&self, system: &System) -> VertexState {
+        let ins = self.ends.iter().filter(|e| e.is_in(system)).count();
+        let outs = self.ends.len() - ins;
+
+        let kind = match (self.ends.len(), ins) {
+            (4, 2) => {
+                // Opposite moments cancel in type I and add up in type II
+                let net: Vec2 = self.ends
+                    .iter()
+                    .map(|e| system.elements()[e.element].magn().normalized() * system.system_signs()[e.element])
+                    .sum();
+                if net.magnitude() < 0.5 { VertexType::TypeI } else { VertexType::TypeII }
+            }
+            (4, 1) | (4, 3) => VertexType::TypeIII,
+            (4, _) => VertexType::TypeIV,
+            (coordination, ins) => VertexType::InOut { coordination, ins },
+        };
+
+        VertexState { ins, outs, charge: ins as i32 - outs as i32, kind }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum VertexType {
+    // Square ice vertices of coordination 4
+    TypeI,
+    TypeII,
+    TypeIII,
+    TypeIV,
+    // Any other coordination, `ins` of the moments pointing in
+    InOut { coordination: usize, ins: usize },
+}
+
+impl VertexType {
+    pub fn coordination(&self) -> usize {
+        match self {
+            VertexType::InOut { coordination, .. } => *coordination,
+            _ => 4,
+        }
+    }
+
+    // |ins - outs|, square ice types do not keep the sign
+    pub fn abs_charge(&self) -> u32 {
+        match self {
+            VertexType::TypeI | VertexType::TypeII => 0,
+            VertexType::TypeIII => 2,
+            VertexType::TypeIV => 4,
+            VertexType::InOut { coordination, ins } => (2 * *ins as i32 - *coordination as i32).unsigned_abs(),
+        }
+    }
+
+    // Ice rule: as many moments in as out, or one more on either side for odd coordination
+    pub fn is_ice_rule(&self) -> bool {
+        self.abs_charge() as usize <= self.coordination() % 2
+    }
+
+    // RGB color for drawing: green and blue for ice rule vertices, orange to red for defects
+    pub fn color(&self) -> Rgb {
+        match self {
+            VertexType::TypeI => (0, 160, 0),
+            VertexType::TypeII => (0, 90, 220),
+            VertexType::TypeIII => (240, 150, 0),
+            VertexType::TypeIV => (220, 0, 0),
+            VertexType::InOut { coordination, ins } => {
+                let charge = 2 * *ins as i32 - *coordination as i32;
+                match (self.is_ice_rule(), charge >= 0) {
+                    (true, true) => (0, 160, 0),
+                    (true, false) => (0, 90, 220),
+                    (false, true) => (220, 0, 0),
+                    (false, false) => (150, 0, 200),
+                }
+            }
+        }
+    }
+}
+
+impl Display for VertexType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VertexType::TypeI => write!(f, "Type I"),
+            VertexType::TypeII => write!(f, "Type II"),
+            VertexType::TypeIII => write!(f, "Type III"),
+            VertexType::TypeIV => write!(f, "Type IV"),
+            VertexType::InOut { coordination, ins } => write!(f, "{}-in/{}-out", ins, coordination - ins),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VertexState {
+    pub ins: usize,
+    pub outs: usize,
+    // Moments in minus moments out
+    pub charge: i32,
+    pub kind: VertexType,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct VertexPopulation {
+    pub counts: BTreeMap<VertexType, usize>,
+    pub total: usize,
+}
+
+impl VertexPopulation {
+    pub fn fraction(&self, kind: VertexType) -> f64 {
+        self.counts.get(&kind).copied().unwrap_or(0) as f64 / self.total.max(1) as f64
+    }
+
+    pub fn ice_rule_fraction(&self) -> f64 {
+        let ice_rule = self.counts.iter().filter(|(k, _)| k.is_ice_rule()).map(|(_, c)| c).sum::<usize>();
+        ice_rule as f64 / self.total.max(1) as f64
+    }
+}
+
+impl Display for VertexPopulation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Vertices: {}", self.total)?;
+        for (kind, count) in &self.counts {
+            writeln!(f, "  {:<12} {:>6} ({:.2}%)", kind.to_string(), count, 100.0 * self.fraction(*kind))?;
+        }
+        write!(f, "Ice rule: {:.2}%", 100.0 * self.ice_rule_fraction())
+    }
+}
+
+// Vertices of the geometry: ends of the elements grouped by distance. They only depend on the
+// elements, so they are found once and classified for every state.
+#[derive(Debug, Clone)]
+pub struct Vertices {
+    pub vertices: Vec<Vertex>,
+    pub reach: f64,
+    pub tolerance: f64,
+}
+
+impl Vertices {
+    pub fn new(system: &System, config: &VertexConfig) -> Self {
+        let size = system.size();
+        let elements = system.elements();
+        let nearest = system.nearest_distance();
+        let reach = config.reach.unwrap_or(nearest / 2.0);
+        let tolerance = config.tolerance.unwrap_or(reach);
+
+        // End 2i is the head of element i, 2i + 1 its tail
+        let end = |index: usize| {
+            let e = elements[index / 2];
+            let direction = e.magn().normalized() * if index.is_multiple_of(2) { 1.0 } else { -1.0 };
+            e.pos.map(|x| x.0) + direction * reach
+        };
+
+        let mut parents: Vec<usize> = (0..2 * size).collect();
+        fn find(parents: &mut [usize], mut x: usize) -> usize {
+            while parents[x] != x {
+                parents[x] = parents[parents[x]];
+                x = parents[x];
+            }
+            x
+        }
+
+        for i in 0..size {
+            let candidates = system.element_neighbors()[i]
+                .iter()
+                .skip(1)
+                .take_while(|(_, d)| d.0 <= 2.0 * reach + tolerance);
+            for (j, _) in candidates.filter(|(j, _)| *j > i) {
+                for a in [2 * i, 2 * i + 1] {
+                    for b in [2 * j, 2 * j + 1] {
+                        if end(a).distance(end(b)) <= tolerance {
+                            let (ra, rb) = (find(&mut parents, a), find(&mut parents, b));
+                            parents[ra] = rb;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for index in 0..2 * size {
+            groups.entry(find(&mut parents, index)).or_default().push(index);
+        }
+
+        let vertices = groups
+            .into_values()
+            .filter(|ends| ends.len() >= config.min_coordination.max(1))
+            .map(|ends| Vertex {
+                position: ends.iter().map(|e| end(*e)).sum::<Vec2>() / ends.len() as f64,
+                ends: ends.iter().map(|e| VertexEnd { element: e / 2, head: e.is_multiple_of(2) }).collect(),
+            })
+            .collect();
+
+        Self { vertices, reach, tolerance }
+    }
+
+    pub fn classify(&self, system: &System) -> Vec<VertexState> {
+        self.vertices.iter().map(|v| v.classify(system)).collect()
+    }
+
+    pub fn population(&self, system: &System) -> VertexPopulation {
+        let mut population = VertexPopulation::default();
+        for state in self.classify(system) {
+            *population.counts.entry(state.kind).or_insert(0) += 1;
+            population.total += 1;
+        }
+        population
+    }
+
+    // Type of the vertex at either end of every element for coloring, the one with the largest
+    // absolute charge when both ends are vertices.
+    pub fn element_types(&self, system: &System) -> Vec<Option<VertexType>> {
+        let mut types: Vec<Option<VertexState>> = vec![None; system.size()];
+        for (vertex, state) in self.vertices.iter().zip(self.classify(system)) {
+            for end in &vertex.ends {
+                let current = &mut types[end.element];
+                if current.is_none_or(|c| state.charge.abs() > c.charge.abs()) {
+                    *current = Some(state);
+                }
+            }
+        }
+        types.into_iter().map(|t| t.map(|s| s.kind)).collect()
+    }
+}
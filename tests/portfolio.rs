@@ -0,0 +1,17 @@
+use system_greedy::cancel::CancellationToken;
+use system_greedy::criteria::StepBudget;
+use system_greedy::generators::LatticeGenerator;
+use system_greedy::portfolio::{runner_portfolio, PortfolioWorker};
+
+#[test]
+fn two_workers_migrate() {
+    let system = LatticeGenerator::trimer(225., 700., 2, 2);
+    // The idle worker keeps the starting state, which greedy improves on
+    let workers = vec![PortfolioWorker::greedy(), PortfolioWorker::new("idle", |_, _, _| {})];
+    let cancel = CancellationToken::new();
+
+    let report = runner_portfolio(system, &workers, StepBudget(4), &cancel, 1, ());
+
+    assert_eq!(report.contributions[0].migrations_received, 0);
+    assert!(report.contributions[1].migrations_received > 0);
+}
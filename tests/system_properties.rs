@@ -0,0 +1,177 @@
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use bitvec::prelude::BitVec;
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::sample::subsequence;
+use system_greedy::cancel::CancellationToken;
+use system_greedy::perebor::{perebor_cluster, perebor_one_thread};
+use system_greedy::system::{System, Vec2};
+use system_greedy::element::Element;
+
+const GRID: usize = 6;
+const EPSILON: f64 = 1e-9;
+
+// Elements on distinct nodes of a unit grid, shifted by less than a quarter of the spacing so that
+// no two of them come closer than 0.5, with moments in any direction.
+fn elements_strategy(max_size: usize) -> impl Strategy<Value = Vec<Element>> {
+    (2..=max_size)
+        .prop_flat_map(|size| {
+            (
+                subsequence((0..GRID * GRID).collect::<Vec<_>>(), size),
+                vec((-0.24..0.24f64, -0.24..0.24f64, 0.0..2.0 * PI), size),
+            )
+        })
+        .prop_map(|(nodes, params)| {
+            nodes
+                .into_iter()
+                .zip(params)
+                .map(|(node, (dx, dy, angle))| {
+                    let pos = Vec2::new((node % GRID) as f64 + dx, (node / GRID) as f64 + dy);
+                    Element::new(pos, Vec2::new(angle.cos(), angle.sin()))
+                })
+                .collect()
+        })
+}
+
+// Elements of a system together with a state and a sequence of spins to flip.
+fn flips_strategy(max_size: usize) -> impl Strategy<Value = (Vec<Element>, BitVec, Vec<usize>)> {
+    elements_strategy(max_size).prop_flat_map(|elements| {
+        let size = elements.len();
+        (
+            Just(elements),
+            vec(any::<bool>(), size).prop_map(|bits| bits.into_iter().collect()),
+            vec(0..size, 0..200),
+        )
+    })
+}
+
+fn recalculated(system: &System) -> System {
+    let mut exact = system.clone();
+    exact.recalculate_energy();
+    exact
+}
+
+fn assert_energies_close(system: &System, exact: &System) -> Result<(), TestCaseError> {
+    prop_assert!((system.energy() - exact.energy()).abs() < EPSILON, "{} != {}", system.energy(), exact.energy());
+    for (i, (a, b)) in system.row_energies().iter().zip(exact.row_energies()).enumerate() {
+        prop_assert!((a - b).abs() < EPSILON, "row {}: {} != {}", i, a, b);
+    }
+    Ok(())
+}
+
+fn temp_mfsys() -> std::path::PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!(
+        "system_properties_{}_{}.mfsys",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+    ))
+}
+
+proptest! {
+    #[test]
+    fn incremental_energies_match_recalculation((elements, state, flips) in flips_strategy(12)) {
+        let mut system = System::new(elements);
+        system.set_system_state(state);
+        let mut reference = system.clone();
+        let mut compensated = system.clone();
+        compensated.use_compensated_rows(true);
+
+        for spin in flips {
+            system.reverse_spin(spin);
+            reference.reverse_spin_reference(spin);
+            compensated.reverse_spin(spin);
+        }
+
+        let exact = recalculated(&system);
+        assert_energies_close(&system, &exact)?;
+        assert_energies_close(&reference, &exact)?;
+        assert_energies_close(&compensated, &exact)?;
+
+        let drift = system.verify();
+        prop_assert!(drift.energy < EPSILON && drift.max_row < EPSILON, "{:?}", drift);
+    }
+
+    #[test]
+    fn flip_delta_predicts_reverse_spin((elements, state, flips) in flips_strategy(12)) {
+        let mut system = System::new(elements);
+        system.set_system_state(state);
+
+        for spin in flips {
+            let expected = system.energy() + system.flip_delta(spin);
+            system.reverse_spin(spin);
+            prop_assert!((system.energy() - expected).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    fn spin_excess_tracks_flips((elements, state, flips) in flips_strategy(12)) {
+        let mut system = System::new(elements);
+        system.set_system_state(state);
+
+        for spin in flips {
+            system.reverse_spin(spin);
+            system.reverse_spin_reference(spin);
+            system.reverse_spin(spin);
+
+            let ones = system.system_state().count_ones() as i32;
+            let zeros = system.system_state().count_zeros() as i32;
+            prop_assert_eq!(system.spin_excess(), ones - zeros);
+        }
+    }
+
+    #[test]
+    fn set_system_state_round_trips((elements, state, flips) in flips_strategy(12)) {
+        let mut system = System::new(elements);
+        let mut flipped = system.clone();
+        flipped.reverse_spins(flips.into_iter());
+
+        system.set_system_state(flipped.system_state().clone());
+        prop_assert_eq!(system.system_state(), flipped.system_state());
+        prop_assert_eq!(system.spin_excess(), flipped.spin_excess());
+        assert_energies_close(&system, &flipped)?;
+
+        for (i, sign) in system.system_signs().iter().enumerate() {
+            prop_assert_eq!(*sign, if system.system_state()[i] { -1.0 } else { 1.0 });
+        }
+
+        system.set_system_state(state.clone());
+        prop_assert_eq!(system.system_state(), &state);
+        assert_energies_close(&system, &recalculated(&system))?;
+    }
+
+    #[test]
+    fn mfsys_round_trips((elements, state, _) in flips_strategy(16)) {
+        let mut system = System::new(elements);
+        system.set_system_state(state);
+
+        let path = temp_mfsys();
+        system.save_mfsys(&path);
+        let loaded = System::load_mfsys(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        prop_assert_eq!(loaded.elements(), system.elements());
+        prop_assert_eq!(loaded.system_state(), system.system_state());
+        prop_assert_eq!(loaded.energy(), system.energy());
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn perebor_cluster_matches_perebor_one_thread((elements, state, _) in flips_strategy(10)) {
+        let mut system = System::new(elements);
+        system.set_system_state(state);
+        let cluster: Vec<_> = (0..system.size()).collect();
+
+        let clustered = perebor_cluster(&system, &cluster, &CancellationToken::new());
+        let exhaustive = perebor_one_thread(&mut system.clone());
+
+        prop_assert!((clustered.energy - exhaustive.energy).abs() < EPSILON, "{} != {}", clustered.energy, exhaustive.energy);
+
+        system.set_system_state(clustered.state);
+        prop_assert!((system.energy() - clustered.energy).abs() < EPSILON);
+    }
+}
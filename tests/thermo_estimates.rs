@@ -0,0 +1,39 @@
+use std::f64::consts::PI;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use system_greedy::thermo::{autocorrelation_time, blocking_error};
+
+const PHI: f64 = 0.9;
+const SAMPLES: usize = 1 << 18;
+
+// x_t = phi x_{t-1} + e_t with unit normal e_t. Its integrated autocorrelation time is
+// (1 + phi) / (2 (1 - phi)) and the error of the mean sqrt(2 tau var(x) / n), var(x) = 1 / (1 - phi^2).
+fn ar1(seed: u64) -> Vec<f64> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut x = 0.0;
+    (0..SAMPLES)
+        .map(|_| {
+            // Box-Muller
+            let (u, v): (f64, f64) = (1.0 - rng.gen::<f64>(), rng.gen());
+            x = PHI * x + (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos();
+            x
+        })
+        .collect()
+}
+
+#[test]
+fn autocorrelation_time_of_ar1() {
+    let tau = autocorrelation_time(&ar1(7));
+    let exact = (1.0 + PHI) / (2.0 * (1.0 - PHI));
+    assert!((tau - exact).abs() < 0.05 * exact, "tau {} against {}", tau, exact);
+}
+
+#[test]
+fn blocking_error_of_ar1() {
+    let error = blocking_error(&ar1(7));
+    let tau = (1.0 + PHI) / (2.0 * (1.0 - PHI));
+    let exact = (2.0 * tau / (1.0 - PHI * PHI) / SAMPLES as f64).sqrt();
+    // The largest error over the blocking levels is taken, which leans high: the last levels have
+    // 16 to 31 blocks and scatter by about 15%
+    assert!(error > 0.9 * exact && error < 1.3 * exact, "error {} against {}", error, exact);
+}